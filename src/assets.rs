@@ -2,40 +2,63 @@ use bevy_asset_loader::prelude::*;
 
 use crate::prelude::*;
 
+mod aseprite;
+
+pub use aseprite::Aseprite;
+
 pub struct AssetPlugin;
 
 impl Plugin for AssetPlugin {
     fn build(&self, app: &mut App) {
-        app.add_loading_state(
-            LoadingState::new(crate::MainState::Loading)
-                .continue_to_state(crate::MainState::Playing)
-                .load_collection::<Player>()
-                .load_collection::<Bullet>()
-                .load_collection::<Misc>(),
-        );
+        app.init_asset::<Aseprite>()
+            .init_asset_loader::<aseprite::AsepriteLoader>()
+            .add_loading_state(
+                LoadingState::new(crate::MainState::Loading)
+                    .continue_to_state(crate::MainState::Playing)
+                    .on_failure_continue_to_state(crate::MainState::Error)
+                    .load_collection::<Player>()
+                    .load_collection::<Bullet>()
+                    .load_collection::<Misc>()
+                    .load_collection::<Sounds>(),
+            );
     }
 }
 
 #[derive(Resource, AssetCollection)]
 pub struct Misc {
-    #[asset(path = "background.png")]
+    /// One row of tile variants per biome
+    #[asset(texture_atlas_layout(tile_size_x = 32., tile_size_y = 32., columns = 4, rows = 3,))]
+    pub background_layout: Handle<TextureAtlasLayout>,
+    #[asset(path = "background_tiles.png")]
     pub background: Handle<Image>,
 }
 
 #[derive(Resource, AssetCollection)]
 pub struct Player {
-    #[asset(texture_atlas_layout(tile_size_x = 32., tile_size_y = 64., columns = 8, rows = 2,))]
-    pub layout: Handle<TextureAtlasLayout>,
-    #[asset(path = "player.png")]
-    pub sprite: Handle<Image>,
+    #[asset(path = "player.ase")]
+    pub sprite: Handle<Aseprite>,
     #[asset(path = "cursor.png")]
     pub cursor: Handle<Image>,
 }
 
 #[derive(Resource, AssetCollection)]
 pub struct Bullet {
-    #[asset(texture_atlas_layout(tile_size_x = 32., tile_size_y = 32., columns = 4, rows = 1,))]
-    pub layout: Handle<TextureAtlasLayout>,
-    #[asset(path = "bullet.png")]
-    pub sprite: Handle<Image>,
+    #[asset(path = "bullet.ase")]
+    pub sprite: Handle<Aseprite>,
+}
+
+#[derive(Resource, AssetCollection)]
+pub struct Sounds {
+    #[asset(path = "sounds/shoot.wav")]
+    pub shoot: Handle<AudioSource>,
+    #[asset(path = "sounds/hit.wav")]
+    pub hit: Handle<AudioSource>,
+    #[asset(path = "sounds/kill.wav")]
+    pub kill: Handle<AudioSource>,
+    #[asset(path = "sounds/connect.wav")]
+    pub connect: Handle<AudioSource>,
+    #[asset(path = "sounds/reject.wav")]
+    pub reject: Handle<AudioSource>,
+    #[asset(path = "sounds/music.wav")]
+    pub music: Handle<AudioSource>,
 }
@@ -0,0 +1,682 @@
+//! Loads Aseprite files straight into a texture atlas, with the tags of the file as named
+//! animations.
+//!
+//! Every frame is flattened from its visible layers and laid out left to right in one image.
+
+use std::fmt;
+use std::io::Read;
+use std::time::Duration;
+
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, AsyncReadExt, LoadContext};
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use bevy::utils::{BoxedFuture, HashMap};
+use flate2::read::ZlibDecoder;
+
+use crate::prelude::*;
+
+const FILE_MAGIC: u16 = 0xA5E0;
+const FRAME_MAGIC: u16 = 0xF1FA;
+
+const OLD_PALETTE_CHUNK: u16 = 0x0004;
+const LAYER_CHUNK: u16 = 0x2004;
+const CEL_CHUNK: u16 = 0x2005;
+const TAGS_CHUNK: u16 = 0x2018;
+const PALETTE_CHUNK: u16 = 0x2019;
+
+#[derive(Asset, TypePath, Debug)]
+pub struct Aseprite {
+    image: Handle<Image>,
+    layout: Handle<TextureAtlasLayout>,
+    durations: Vec<Duration>,
+    tags: HashMap<String, Tag>,
+}
+
+/// One step of an animation, `index` is into the atlas layout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    pub index: usize,
+    pub duration: Duration,
+}
+
+impl Aseprite {
+    pub const fn image(&self) -> &Handle<Image> {
+        &self.image
+    }
+
+    pub const fn layout(&self) -> &Handle<TextureAtlasLayout> {
+        &self.layout
+    }
+
+    /// The frames of a tag in the order they play, `None` if the file has no such tag
+    pub fn clip(&self, tag: &str) -> Option<Vec<Frame>> {
+        self.tags.get(tag).map(|tag| {
+            tag.order()
+                .into_iter()
+                .map(|index| self.frame(index))
+                .collect()
+        })
+    }
+
+    /// Every frame of the file, for files without tags
+    pub fn all_frames(&self) -> Vec<Frame> {
+        (0..self.durations.len())
+            .map(|index| self.frame(index))
+            .collect()
+    }
+
+    fn frame(&self, index: usize) -> Frame {
+        Frame {
+            index,
+            duration: self.durations[index],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum AsepriteError {
+    Io(std::io::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for AsepriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "couldn't read aseprite file: {err}"),
+            Self::Invalid(message) => write!(f, "invalid aseprite file: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for AsepriteError {}
+
+impl From<std::io::Error> for AsepriteError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+#[derive(Default)]
+pub struct AsepriteLoader;
+
+impl AssetLoader for AsepriteLoader {
+    type Asset = Aseprite;
+    type Settings = ();
+    type Error = AsepriteError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Aseprite, AsepriteError>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            let file = parse(&bytes)?;
+
+            let frames = file.frames.len();
+            let width = file
+                .width
+                .checked_mul(frames)
+                .and_then(|width| u32::try_from(width).ok())
+                .ok_or_else(|| AsepriteError::Invalid("sprite sheet is too wide".into()))?;
+            let mut data = vec![0; frames * file.width * file.height * 4];
+            let row = file.width * 4;
+            for (frame, pixels) in file.frames.iter().enumerate() {
+                for y in 0..file.height {
+                    let start = (y * frames + frame) * row;
+                    data[start..start + row].copy_from_slice(&pixels[y * row..(y + 1) * row]);
+                }
+            }
+            let image = Image::new(
+                Extent3d {
+                    width,
+                    height: file.height as u32,
+                    depth_or_array_layers: 1,
+                },
+                TextureDimension::D2,
+                data,
+                TextureFormat::Rgba8UnormSrgb,
+                RenderAssetUsages::default(),
+            );
+            let layout = TextureAtlasLayout::from_grid(
+                Vec2::new(file.width as f32, file.height as f32),
+                frames,
+                1,
+                None,
+                None,
+            );
+
+            Ok(Aseprite {
+                image: load_context.add_labeled_asset("image".into(), image),
+                layout: load_context.add_labeled_asset("layout".into(), layout),
+                durations: file.durations,
+                tags: file.tags,
+            })
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ase", "aseprite"]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Forward,
+    Reverse,
+    PingPong,
+    PingPongReverse,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Tag {
+    from: usize,
+    to: usize,
+    direction: Direction,
+}
+
+impl Tag {
+    fn order(&self) -> Vec<usize> {
+        let forward: Vec<_> = (self.from..=self.to).collect();
+        let backward: Vec<_> = forward.iter().rev().copied().collect();
+        // Ping pong doesn't repeat the frames at either end
+        let inner = |frames: &[usize]| {
+            frames
+                .iter()
+                .skip(1)
+                .take(frames.len().saturating_sub(2))
+                .copied()
+                .collect::<Vec<_>>()
+        };
+        match self.direction {
+            Direction::Forward => forward,
+            Direction::Reverse => backward,
+            Direction::PingPong => [forward, inner(&backward)].concat(),
+            Direction::PingPongReverse => [backward, inner(&forward)].concat(),
+        }
+    }
+}
+
+/// A flattened file, every frame is `width * height` RGBA pixels
+struct File {
+    width: usize,
+    height: usize,
+    frames: Vec<Vec<u8>>,
+    durations: Vec<Duration>,
+    tags: HashMap<String, Tag>,
+}
+
+struct Layer {
+    visible: bool,
+    opacity: u8,
+}
+
+#[derive(Clone)]
+struct Cel {
+    layer: u16,
+    x: i16,
+    y: i16,
+    z_index: i16,
+    opacity: u8,
+    width: u16,
+    height: u16,
+    /// In the color depth of the file
+    pixels: Vec<u8>,
+}
+
+struct Bytes<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Bytes<'a> {
+    const fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn take(&mut self, amount: usize) -> Result<&'a [u8], AsepriteError> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + amount)
+            .ok_or_else(|| AsepriteError::Invalid("file ends early".into()))?;
+        self.pos += amount;
+        Ok(bytes)
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = self.data.get(self.pos..).unwrap_or_default();
+        self.pos = self.data.len();
+        rest
+    }
+
+    fn u8(&mut self) -> Result<u8, AsepriteError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, AsepriteError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn i16(&mut self) -> Result<i16, AsepriteError> {
+        let bytes = self.take(2)?;
+        Ok(i16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, AsepriteError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn string(&mut self) -> Result<String, AsepriteError> {
+        let len = self.u16()?;
+        Ok(String::from_utf8_lossy(self.take(len.into())?).into_owned())
+    }
+}
+
+/// Everything read from the chunks so far
+#[derive(Default)]
+struct Parser {
+    width: usize,
+    height: usize,
+    /// Bytes per pixel
+    depth: usize,
+    layer_opacity_valid: bool,
+    transparent: u8,
+    layers: Vec<Layer>,
+    /// Visibility of the group at each nesting level, hidden groups hide their children
+    groups: Vec<bool>,
+    palette: Vec<[u8; 4]>,
+    tags: HashMap<String, Tag>,
+    cels: Vec<Vec<Cel>>,
+    durations: Vec<Duration>,
+}
+
+fn parse(data: &[u8]) -> Result<File, AsepriteError> {
+    let mut parser = Parser::default();
+    let mut bytes = Bytes::new(data);
+    let frames = parser.header(&mut Bytes::new(bytes.take(128)?))?;
+    for _ in 0..frames {
+        parser.frame(&mut bytes)?;
+    }
+
+    if let Some((name, _)) = parser
+        .tags
+        .iter()
+        .find(|(_, tag)| tag.to >= parser.durations.len())
+    {
+        return Err(AsepriteError::Invalid(format!(
+            "tag {name} is past the last frame"
+        )));
+    }
+
+    let frames = (0..parser.cels.len())
+        .map(|frame| parser.flatten(frame))
+        .collect();
+    Ok(File {
+        width: parser.width,
+        height: parser.height,
+        frames,
+        durations: parser.durations,
+        tags: parser.tags,
+    })
+}
+
+impl Parser {
+    /// Returns the amount of frames
+    fn header(&mut self, header: &mut Bytes) -> Result<u16, AsepriteError> {
+        header.u32()?;
+        if header.u16()? != FILE_MAGIC {
+            return Err(AsepriteError::Invalid("not an aseprite file".into()));
+        }
+        let frames = header.u16()?;
+        self.width = header.u16()?.into();
+        self.height = header.u16()?.into();
+        if frames == 0 || self.width == 0 || self.height == 0 {
+            return Err(AsepriteError::Invalid(format!(
+                "empty sprite, {frames} frames of {}x{}",
+                self.width, self.height
+            )));
+        }
+        self.depth = match header.u16()? {
+            depth @ (8 | 16 | 32) => usize::from(depth / 8),
+            depth => {
+                return Err(AsepriteError::Invalid(format!(
+                    "unknown color depth {depth}"
+                )))
+            }
+        };
+        self.layer_opacity_valid = header.u32()? & 1 != 0;
+        header.take(10)?;
+        self.transparent = header.u8()?;
+        Ok(frames)
+    }
+
+    fn frame(&mut self, bytes: &mut Bytes) -> Result<(), AsepriteError> {
+        let frame_start = bytes.pos;
+        let frame_size = bytes.u32()? as usize;
+        if bytes.u16()? != FRAME_MAGIC {
+            return Err(AsepriteError::Invalid("bad frame header".into()));
+        }
+        let old_chunks = bytes.u16()?;
+        self.durations
+            .push(Duration::from_millis(bytes.u16()?.into()));
+        bytes.take(2)?;
+        let chunks = match bytes.u32()? {
+            0 => old_chunks.into(),
+            chunks => chunks,
+        };
+
+        self.cels.push(Vec::new());
+        for _ in 0..chunks {
+            let chunk_size = bytes.u32()? as usize;
+            let kind = bytes.u16()?;
+            let mut chunk = Bytes::new(bytes.take(chunk_size.saturating_sub(6))?);
+            match kind {
+                LAYER_CHUNK => self.layer(&mut chunk)?,
+                CEL_CHUNK => self.cel(&mut chunk)?,
+                PALETTE_CHUNK => self.palette(&mut chunk)?,
+                // Newer files have both palettes, the old one is only for old versions
+                OLD_PALETTE_CHUNK if self.palette.is_empty() => self.old_palette(&mut chunk)?,
+                TAGS_CHUNK => self.tags(&mut chunk)?,
+                _ => {}
+            }
+        }
+        bytes.pos = frame_start + frame_size;
+        Ok(())
+    }
+
+    fn layer(&mut self, chunk: &mut Bytes) -> Result<(), AsepriteError> {
+        let flags = chunk.u16()?;
+        let layer_type = chunk.u16()?;
+        let level = usize::from(chunk.u16()?);
+        chunk.take(6)?;
+        let opacity = chunk.u8()?;
+
+        let parent_visible = level == 0 || self.groups.get(level - 1).copied().unwrap_or(true);
+        let visible = flags & 1 != 0 && parent_visible;
+        self.groups.truncate(level);
+        self.groups.push(visible);
+        self.layers.push(Layer {
+            // Groups have nothing to draw themselves
+            visible: visible && layer_type != 1,
+            opacity: if self.layer_opacity_valid {
+                opacity
+            } else {
+                255
+            },
+        });
+        Ok(())
+    }
+
+    fn cel(&mut self, chunk: &mut Bytes) -> Result<(), AsepriteError> {
+        let layer = chunk.u16()?;
+        let x = chunk.i16()?;
+        let y = chunk.i16()?;
+        let opacity = chunk.u8()?;
+        let cel_type = chunk.u16()?;
+        let z_index = chunk.i16()?;
+        chunk.take(5)?;
+
+        let cel = match cel_type {
+            0 | 2 => {
+                let width = chunk.u16()?;
+                let height = chunk.u16()?;
+                let size = usize::from(width) * usize::from(height) * self.depth;
+                let pixels = if cel_type == 0 {
+                    chunk.take(size)?.to_vec()
+                } else {
+                    let mut pixels = Vec::with_capacity(size);
+                    ZlibDecoder::new(chunk.rest()).read_to_end(&mut pixels)?;
+                    pixels
+                };
+                Cel {
+                    layer,
+                    x,
+                    y,
+                    z_index,
+                    opacity,
+                    width,
+                    height,
+                    pixels,
+                }
+            }
+            1 => {
+                let linked = usize::from(chunk.u16()?);
+                self.cels
+                    .get(linked)
+                    .and_then(|frame| frame.iter().find(|cel| cel.layer == layer))
+                    .ok_or_else(|| {
+                        AsepriteError::Invalid(format!("cel linked to missing frame {linked}"))
+                    })?
+                    .clone()
+            }
+            // Tilemaps aren't used by anything yet
+            _ => return Ok(()),
+        };
+        if let Some(frame) = self.cels.last_mut() {
+            frame.push(cel);
+        }
+        Ok(())
+    }
+
+    fn palette(&mut self, chunk: &mut Bytes) -> Result<(), AsepriteError> {
+        let size = chunk.u32()? as usize;
+        let first = chunk.u32()? as usize;
+        let last = chunk.u32()? as usize;
+        chunk.take(8)?;
+        // Indexed colors are a byte, anything bigger is a broken file
+        if size > 256 || last >= size {
+            return Err(AsepriteError::Invalid(format!(
+                "palette of {size} colors setting {first} to {last}"
+            )));
+        }
+        self.palette.resize(size.max(self.palette.len()), [0; 4]);
+        for index in first..=last {
+            let flags = chunk.u16()?;
+            let color = chunk.take(4)?;
+            if let Some(entry) = self.palette.get_mut(index) {
+                *entry = [color[0], color[1], color[2], color[3]];
+            }
+            if flags & 1 != 0 {
+                chunk.string()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn old_palette(&mut self, chunk: &mut Bytes) -> Result<(), AsepriteError> {
+        let mut index = 0;
+        for _ in 0..chunk.u16()? {
+            index += usize::from(chunk.u8()?);
+            let count = match chunk.u8()? {
+                0 => 256,
+                count => usize::from(count),
+            };
+            for _ in 0..count {
+                let color = chunk.take(3)?;
+                self.palette
+                    .resize(self.palette.len().max(index + 1), [0; 4]);
+                self.palette[index] = [color[0], color[1], color[2], 255];
+                index += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn tags(&mut self, chunk: &mut Bytes) -> Result<(), AsepriteError> {
+        let count = chunk.u16()?;
+        chunk.take(8)?;
+        for _ in 0..count {
+            let from = usize::from(chunk.u16()?);
+            let to = usize::from(chunk.u16()?);
+            let direction = match chunk.u8()? {
+                1 => Direction::Reverse,
+                2 => Direction::PingPong,
+                3 => Direction::PingPongReverse,
+                _ => Direction::Forward,
+            };
+            chunk.take(12)?;
+            let name = chunk.string()?;
+            self.tags.insert(
+                name,
+                Tag {
+                    from,
+                    to: to.max(from),
+                    direction,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    fn color(&self, pixel: &[u8]) -> [u8; 4] {
+        match self.depth {
+            4 => [pixel[0], pixel[1], pixel[2], pixel[3]],
+            2 => [pixel[0], pixel[0], pixel[0], pixel[1]],
+            _ if pixel[0] == self.transparent => [0; 4],
+            _ => self
+                .palette
+                .get(usize::from(pixel[0]))
+                .copied()
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Draws the visible layers of a frame on top of each other
+    fn flatten(&self, frame: usize) -> Vec<u8> {
+        let mut cels: Vec<_> = self.cels[frame].iter().collect();
+        cels.sort_by_key(|cel| (i32::from(cel.layer) + i32::from(cel.z_index), cel.z_index));
+
+        let mut canvas = vec![0; self.width * self.height * 4];
+        for cel in cels {
+            let Some(layer) = self
+                .layers
+                .get(usize::from(cel.layer))
+                .filter(|layer| layer.visible)
+            else {
+                continue;
+            };
+            let opacity = f32::from(cel.opacity) / 255.0 * f32::from(layer.opacity) / 255.0;
+            for cel_y in 0..cel.height {
+                for cel_x in 0..cel.width {
+                    let (Ok(x), Ok(y)) = (
+                        usize::try_from(i32::from(cel.x) + i32::from(cel_x)),
+                        usize::try_from(i32::from(cel.y) + i32::from(cel_y)),
+                    ) else {
+                        continue;
+                    };
+                    let index = (usize::from(cel_y) * usize::from(cel.width) + usize::from(cel_x))
+                        * self.depth;
+                    let Some(pixel) = cel.pixels.get(index..index + self.depth) else {
+                        continue;
+                    };
+                    if x >= self.width || y >= self.height {
+                        continue;
+                    }
+                    let at = (y * self.width + x) * 4;
+                    blend(&mut canvas[at..at + 4], self.color(pixel), opacity);
+                }
+            }
+        }
+        canvas
+    }
+}
+
+/// Normal blending of `source` over `target`
+fn blend(target: &mut [u8], source: [u8; 4], opacity: f32) {
+    let alpha = f32::from(source[3]) / 255.0 * opacity;
+    let below = f32::from(target[3]) / 255.0 * (1.0 - alpha);
+    let total = alpha + below;
+    if total <= 0.0 {
+        return;
+    }
+    for channel in 0..3 {
+        let mixed = f32::from(source[channel]) * alpha + f32::from(target[channel]) * below;
+        target[channel] = (mixed / total).round() as u8;
+    }
+    target[3] = (total * 255.0).round() as u8;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_player() {
+        let file = parse(include_bytes!("../../assets/player.ase")).map_err(|err| err.to_string());
+        let Ok(file) = file else {
+            return assert_eq!(file.err(), None);
+        };
+        assert_eq!((file.width, file.height, file.frames.len()), (32, 64, 12));
+        assert_eq!(file.tags["Idle"].order(), (0..=7).collect::<Vec<_>>());
+        assert_eq!(file.tags["Moving"].order(), (8..=11).collect::<Vec<_>>());
+        assert!(file
+            .durations
+            .iter()
+            .all(|&duration| duration == Duration::from_millis(150)));
+        // Every frame has something drawn in it
+        assert!(file
+            .frames
+            .iter()
+            .all(|frame| frame.chunks_exact(4).any(|pixel| pixel[3] > 0)));
+    }
+
+    #[test]
+    fn ping_pong_skips_the_ends() {
+        let tag = Tag {
+            from: 2,
+            to: 5,
+            direction: Direction::PingPong,
+        };
+        assert_eq!(tag.order(), [2, 3, 4, 5, 4, 3]);
+        let single = Tag {
+            from: 1,
+            to: 1,
+            direction: Direction::PingPongReverse,
+        };
+        assert_eq!(single.order(), [1]);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(parse(&[0; 200]).is_err());
+        assert!(parse(&[]).is_err());
+    }
+
+    #[test]
+    fn rejects_empty_sprites() {
+        let player = include_bytes!("../../assets/player.ase");
+        // Frame count, width and height come right after the size and magic number
+        for offset in [6, 8, 10] {
+            let mut bytes = player.to_vec();
+            bytes[offset..offset + 2].copy_from_slice(&[0, 0]);
+            assert!(parse(&bytes).is_err(), "zeroed bytes at {offset}");
+        }
+    }
+
+    #[test]
+    fn rejects_huge_palettes() {
+        let chunk = |size: u32, first: u32, last: u32| {
+            let mut bytes = Vec::new();
+            for value in [size, first, last] {
+                bytes.extend(value.to_le_bytes());
+            }
+            bytes.extend([0; 8]);
+            for _ in first..=last {
+                bytes.extend([0, 0, 1, 2, 3, 4]);
+            }
+            bytes
+        };
+        let mut parser = Parser::default();
+        assert!(parser.palette(&mut Bytes::new(&chunk(4, 0, 3))).is_ok());
+        assert_eq!(parser.palette, [[1, 2, 3, 4]; 4]);
+        assert!(parser
+            .palette(&mut Bytes::new(&chunk(u32::MAX, 0, 0)))
+            .is_err());
+        assert!(parser.palette(&mut Bytes::new(&chunk(4, 2, 4))).is_err());
+    }
+}
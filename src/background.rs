@@ -1,12 +1,72 @@
-use bevy_prng::WyRand;
-use rand::Rng;
-
 use crate::prelude::*;
+use crate::world::{grid_hash, RunSeed};
 use crate::{assets, MainState, ZIndex};
 
 const SIZE: f32 = 32.0;
 const SCALE: f32 = 2.0;
 const TILE_SIZE: f32 = SIZE * SCALE;
+/// Tile variants per biome, the columns of the atlas
+const VARIANTS: usize = 4;
+/// Width of a biome noise cell in tiles, roughly how large biomes get
+const BIOME_CELL: i32 = 24;
+
+/// One scrolling layer of the background
+struct LayerSettings {
+    name: &'static str,
+    /// How much of the camera movement the layer follows, 1 moves with the world while less
+    /// looks further away and more looks closer
+    factor: f32,
+    /// Distance between the cells of the layer
+    spacing: f32,
+    z: ZIndex,
+    look: LayerLook,
+}
+
+enum LayerLook {
+    /// The biome tiles everything stands on
+    Floor,
+    /// Shapes scattered over the layer, `chance` out of 16 cells get one
+    Specks {
+        chance: u64,
+        color: Color,
+        min_size: f32,
+        max_size: f32,
+    },
+}
+
+const LAYERS: [LayerSettings; 3] = [
+    LayerSettings {
+        name: "Dust",
+        factor: 0.6,
+        spacing: 48.0,
+        z: ZIndex::Dust,
+        look: LayerLook::Specks {
+            chance: 6,
+            color: Color::rgba(0.9, 0.9, 1.0, 0.25),
+            min_size: 2.0,
+            max_size: 5.0,
+        },
+    },
+    LayerSettings {
+        name: "Floor",
+        factor: 1.0,
+        spacing: TILE_SIZE,
+        z: ZIndex::Background,
+        look: LayerLook::Floor,
+    },
+    LayerSettings {
+        name: "Decals",
+        factor: 1.4,
+        spacing: 160.0,
+        z: ZIndex::Decals,
+        look: LayerLook::Specks {
+            chance: 3,
+            color: Color::rgba(0.05, 0.1, 0.05, 0.18),
+            min_size: 40.0,
+            max_size: 110.0,
+        },
+    },
+];
 
 pub struct BackgroundPlugin;
 
@@ -14,7 +74,13 @@ impl Plugin for BackgroundPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<CurrentSize>().add_systems(
             Update,
-            (spawn_background, move_background).run_if(in_state(MainState::Playing)),
+            (
+                spawn_background,
+                move_background,
+                (style_tiles, style_specks),
+            )
+                .chain()
+                .run_if(in_state(MainState::Playing)),
         );
     }
 }
@@ -22,109 +88,373 @@ impl Plugin for BackgroundPlugin {
 #[derive(Component)]
 struct Background;
 
+/// Visible area the layers were spawned for, in whole floor tiles so zooming doesn't respawn
+/// them every frame
 #[derive(Resource, Default, Debug)]
 struct CurrentSize(IVec2);
 
+/// Parent of every cell in a layer, `settings` indexes into `LAYERS`
+#[derive(Component)]
+struct Layer {
+    settings: usize,
+    size: IVec2,
+}
+
+/// Grid coordinate of a cell within its layer
 #[derive(Component)]
 struct Tile(IVec2);
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Biome {
+    Meadow,
+    Ashlands,
+    Dunes,
+}
+
+impl Biome {
+    /// How often each variant shows up, the plain tile should be by far the most common
+    const fn weights(self) -> [u64; VARIANTS] {
+        match self {
+            Self::Meadow => [10, 3, 1, 1],
+            Self::Ashlands => [8, 2, 2, 1],
+            Self::Dunes => [10, 1, 1, 3],
+        }
+    }
+
+    /// The biome at a tile, from smoothed value noise so biomes form large regions
+    fn at(seed: RunSeed, tile: IVec2) -> Self {
+        // Offset so the biome noise isn't correlated with the tile looks
+        let seed = RunSeed(seed.0 ^ 0xB10E);
+        let cell = tile.div_euclid(IVec2::splat(BIOME_CELL));
+        let within = (tile - cell * BIOME_CELL).as_vec2() / BIOME_CELL as f32;
+        let smooth = within * within * (3.0 - 2.0 * within);
+        let corner =
+            |offset: IVec2| (grid_hash(seed, cell + offset) >> 40) as f32 / (1 << 24) as f32;
+        let bottom = corner(IVec2::ZERO).lerp(corner(IVec2::X), smooth.x);
+        let top = corner(IVec2::Y).lerp(corner(IVec2::ONE), smooth.x);
+        match bottom.lerp(top, smooth.y) {
+            noise if noise < 0.4 => Self::Meadow,
+            noise if noise < 0.6 => Self::Dunes,
+            _ => Self::Ashlands,
+        }
+    }
+}
+
+/// How a tile looks, picked from its coordinate so the floor never changes under the player
+#[derive(Debug, PartialEq, Eq)]
+struct TileLook {
+    biome: Biome,
+    variant: usize,
+    quarter_turns: u64,
+    flip_x: bool,
+    flip_y: bool,
+}
+
+impl TileLook {
+    const fn atlas_index(&self) -> usize {
+        self.biome as usize * VARIANTS + self.variant
+    }
+}
+
+fn tile_look(seed: RunSeed, tile: IVec2) -> TileLook {
+    let hash = grid_hash(seed, tile);
+    let biome = Biome::at(seed, tile);
+    let weights = biome.weights();
+    let mut roll = (hash >> 4) % weights.iter().sum::<u64>();
+    let variant = weights
+        .iter()
+        .position(|&weight| {
+            if roll < weight {
+                return true;
+            }
+            roll -= weight;
+            false
+        })
+        .unwrap_or_default();
+    TileLook {
+        biome,
+        variant,
+        quarter_turns: hash & 0b11,
+        flip_x: hash & 0b100 != 0,
+        flip_y: hash & 0b1000 != 0,
+    }
+}
+
+/// A decoration in a cell of a `LayerLook::Specks` layer, `None` for an empty cell
+fn speck_look(seed: RunSeed, layer: usize, tile: IVec2, spacing: f32) -> Option<(Vec2, f32, f32)> {
+    let LayerLook::Specks {
+        chance,
+        min_size,
+        max_size,
+        ..
+    } = LAYERS[layer].look
+    else {
+        return None;
+    };
+    let hash = grid_hash(RunSeed(seed.0 ^ layer as u64), tile);
+    if hash & 0xF >= chance {
+        return None;
+    }
+    let unit = |shift: u32| ((hash >> shift) & 0xFF) as f32 / 255.0;
+    let offset = (Vec2::new(unit(8), unit(16)) - 0.5) * spacing;
+    let size = min_size + (max_size - min_size) * unit(24);
+    let rotation = unit(32) * std::f32::consts::TAU;
+    Some((tile.as_vec2() * spacing + offset, size, rotation))
+}
+
 fn spawn_background(
     mut commands: Commands,
     misc: Res<assets::Misc>,
-    window: Query<&Window, Changed<Window>>,
+    window: Query<&Window>,
+    camera: Query<(&Transform, &OrthographicProjection), With<Camera>>,
     backgrounds: Query<Entity, With<Background>>,
     mut size: ResMut<CurrentSize>,
-    mut rng: ResMut<GlobalEntropy<WyRand>>,
 ) {
     let Ok(window) = window.get_single() else {
         return;
     };
+    let zoom = camera
+        .get_single()
+        .map_or(1.0, |(_, projection)| projection.scale);
 
-    let width = window.width();
-    let height = window.height();
-
-    let x_amount = (width / TILE_SIZE).ceil() as i32 + 3;
-    let y_amount = (height / TILE_SIZE).ceil() as i32 + 3;
-
-    let size_vec = IVec2::new(x_amount, y_amount);
-    if size_vec == size.0 {
+    let tiles = (Vec2::new(window.width(), window.height()) * zoom / TILE_SIZE)
+        .ceil()
+        .as_ivec2();
+    if tiles == size.0 {
         return;
     }
-    size.0 = size_vec;
+    size.0 = tiles;
+    let view_size = tiles.as_vec2() * TILE_SIZE;
 
     for background in &backgrounds {
         commands.entity(background).despawn_recursive();
     }
 
-    commands
+    let camera_pos = camera
+        .get_single()
+        .map_or(Vec2::ZERO, |(camera, _)| camera.translation.truncate());
+
+    let parent = commands
         .spawn((
             Gc(MainState::Playing),
             Background,
             Name::new("Background"),
             SpatialBundle::default(),
         ))
-        .with_children(|children| {
-            for x_index in 0..x_amount {
-                for y_index in 0..y_amount {
-                    let x = x_index as f32 * TILE_SIZE - width / 2.;
-                    let y = y_index as f32 * TILE_SIZE - height / 2.;
-                    children.spawn((
-                        SpriteBundle {
-                            texture: misc.background.clone(),
-                            transform: Transform {
-                                translation: Vec3::new(x, y, ZIndex::Background.into()),
-                                scale: Vec3::new(SCALE, SCALE, 1.0),
-                                rotation: Quat::from_rotation_z(
-                                    rng.gen_range(0..4) as f32 * std::f32::consts::FRAC_PI_2,
-                                ),
-                            },
-                            sprite: Sprite {
-                                flip_x: rng.gen_bool(0.5),
-                                flip_y: rng.gen_bool(0.5),
-                                ..default()
-                            },
-                            ..default()
-                        },
-                        Name::new("Tile"),
-                        Tile(IVec2::new(x_index, y_index)),
-                    ));
+        .id();
+
+    for (index, settings) in LAYERS.iter().enumerate() {
+        let layer_size = (view_size / settings.spacing).ceil().as_ivec2() + 3;
+        let camera_tile = (camera_pos * settings.factor / settings.spacing)
+            .round()
+            .as_ivec2();
+        let corner = camera_tile - layer_size / 2;
+
+        commands
+            .spawn((
+                Layer {
+                    settings: index,
+                    size: layer_size,
+                },
+                Name::new(settings.name),
+                SpatialBundle::from_transform(Transform::from_translation(
+                    (camera_pos * (1.0 - settings.factor)).extend(0.0),
+                )),
+            ))
+            .set_parent(parent)
+            .with_children(|children| {
+                for x_index in 0..layer_size.x {
+                    for y_index in 0..layer_size.y {
+                        let tile = corner + IVec2::new(x_index, y_index);
+                        let translation =
+                            (tile.as_vec2() * settings.spacing).extend(settings.z.into());
+                        match settings.look {
+                            LayerLook::Floor => children.spawn((
+                                SpriteBundle {
+                                    texture: misc.background.clone(),
+                                    transform: Transform {
+                                        translation,
+                                        scale: Vec3::new(SCALE, SCALE, 1.0),
+                                        ..default()
+                                    },
+                                    ..default()
+                                },
+                                TextureAtlas {
+                                    layout: misc.background_layout.clone(),
+                                    index: 0,
+                                },
+                                Name::new("Tile"),
+                                Tile(tile),
+                            )),
+                            LayerLook::Specks { color, .. } => children.spawn((
+                                SpriteBundle {
+                                    transform: Transform::from_translation(translation),
+                                    sprite: Sprite { color, ..default() },
+                                    ..default()
+                                },
+                                Name::new("Speck"),
+                                Tile(tile),
+                            )),
+                        };
+                    }
                 }
-            }
-        });
+            });
+    }
 }
 
 fn move_background(
-    camera: Query<&Transform, (With<Camera>, Changed<Transform>, Without<Tile>)>,
-    mut tiles: Query<(&mut Tile, &mut Transform)>,
-    size: Res<CurrentSize>,
+    camera: Query<&Transform, (With<Camera>, Changed<Transform>)>,
+    mut layers: Query<(&Layer, &mut Transform, &Children), Without<Camera>>,
+    mut tiles: Query<(&mut Tile, &mut Transform), (Without<Layer>, Without<Camera>)>,
 ) {
     let Ok(camera_trans) = camera.get_single() else {
         return;
     };
 
-    let x_size = size.0.x as f32 * TILE_SIZE;
-    let y_size = size.0.y as f32 * TILE_SIZE;
+    for (layer, mut layer_trans, children) in &mut layers {
+        let settings = &LAYERS[layer.settings];
+        let world_camera = camera_trans.translation.truncate();
+        layer_trans.translation = (world_camera * (1.0 - settings.factor)).extend(0.0);
+
+        let x_size = layer.size.x as f32 * settings.spacing;
+        let y_size = layer.size.y as f32 * settings.spacing;
+
+        let x_bound = x_size / 2.;
+        let y_bound = y_size / 2.;
+
+        // Where the camera is as seen from inside the layer
+        let camera_pos = world_camera * settings.factor;
+
+        let mut iter = tiles.iter_many_mut(children);
+        while let Some((mut tile, mut trans)) = iter.fetch_next() {
+            let pos = tile.0.as_vec2() * settings.spacing;
+            if pos.x > camera_pos.x + x_bound {
+                tile.0.x -= layer.size.x;
+                trans.translation -= Vec3::new(x_size, 0.0, 0.0);
+            } else if pos.x < camera_pos.x - x_bound {
+                tile.0.x += layer.size.x;
+                trans.translation += Vec3::new(x_size, 0.0, 0.0);
+            }
+            if pos.y > camera_pos.y + y_bound {
+                tile.0.y -= layer.size.y;
+                trans.translation -= Vec3::new(0.0, y_size, 0.0);
+            } else if pos.y < camera_pos.y - y_bound {
+                tile.0.y += layer.size.y;
+                trans.translation += Vec3::new(0.0, y_size, 0.0);
+            }
+        }
+    }
+}
+
+/// Restyles tiles that just spawned or wrapped around, and all of them when a new run starts
+fn style_tiles(
+    seed: Res<RunSeed>,
+    mut tiles: Query<(Ref<Tile>, &mut Transform, &mut Sprite, &mut TextureAtlas)>,
+) {
+    for (tile, mut trans, mut sprite, mut atlas) in &mut tiles {
+        if !(tile.is_changed() || seed.is_changed()) {
+            continue;
+        }
+        let look = tile_look(*seed, tile.0);
+        trans.rotation =
+            Quat::from_rotation_z(look.quarter_turns as f32 * std::f32::consts::FRAC_PI_2);
+        sprite.flip_x = look.flip_x;
+        sprite.flip_y = look.flip_y;
+        atlas.index = look.atlas_index();
+    }
+}
+
+fn style_specks(
+    seed: Res<RunSeed>,
+    layers: Query<&Layer>,
+    mut specks: Query<
+        (
+            Ref<Tile>,
+            &Parent,
+            &mut Transform,
+            &mut Sprite,
+            &mut Visibility,
+        ),
+        Without<TextureAtlas>,
+    >,
+) {
+    for (tile, parent, mut trans, mut sprite, mut visibility) in &mut specks {
+        if !(tile.is_changed() || seed.is_changed()) {
+            continue;
+        }
+        let Ok(layer) = layers.get(parent.get()) else {
+            continue;
+        };
+        let Some((pos, size, rotation)) = speck_look(
+            *seed,
+            layer.settings,
+            tile.0,
+            LAYERS[layer.settings].spacing,
+        ) else {
+            *visibility = Visibility::Hidden;
+            continue;
+        };
+        *visibility = Visibility::Inherited;
+        trans.translation = pos.extend(trans.translation.z);
+        trans.rotation = Quat::from_rotation_z(rotation);
+        sprite.custom_size = Some(Vec2::splat(size));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let x_bound = x_size / 2.;
-    let y_bound = y_size / 2.;
+    #[test]
+    fn tiles_look_the_same_every_time() {
+        let seed = RunSeed(42);
+        let looks = |seed| {
+            (-8..8)
+                .map(|x| tile_look(seed, IVec2::new(x, 3)))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(looks(seed), looks(seed));
+        // A repeating floor would give every tile the same look
+        assert!(looks(seed).iter().any(|look| *look != looks(seed)[0]));
+    }
 
-    let camera_pos = camera_trans.translation.truncate();
+    #[test]
+    fn biomes_form_regions() {
+        let seed = RunSeed(3);
+        let biomes = (-200..200)
+            .map(|x| Biome::at(seed, IVec2::new(x, 0)))
+            .collect::<Vec<_>>();
+        // Neighbouring tiles mostly share a biome, but the world isn't one biome
+        let borders = biomes.windows(2).filter(|pair| pair[0] != pair[1]).count();
+        assert!(borders > 0);
+        assert!(borders < biomes.len() / 8);
+    }
 
-    for (mut tile, mut trans) in &mut tiles {
-        let pos = trans.translation.truncate();
-        if pos.x > camera_pos.x + x_bound {
-            tile.0.x -= size.0.x;
-            trans.translation -= Vec3::new(x_size, 0.0, 0.0);
-        } else if pos.x < camera_pos.x - x_bound {
-            tile.0.x += size.0.x;
-            trans.translation += Vec3::new(x_size, 0.0, 0.0);
+    #[test]
+    fn variants_follow_weights() {
+        let seed = RunSeed(9);
+        let mut counts = [0; VARIANTS];
+        for x in 0..64 {
+            for y in 0..64 {
+                counts[tile_look(seed, IVec2::new(x, y)).variant] += 1;
+            }
         }
-        if pos.y > camera_pos.y + y_bound {
-            tile.0.y -= size.0.y;
-            trans.translation -= Vec3::new(0.0, y_size, 0.0);
-        } else if pos.y < camera_pos.y - y_bound {
-            tile.0.y += size.0.y;
-            trans.translation += Vec3::new(0.0, y_size, 0.0);
+        assert!(counts.iter().all(|&count| count > 0));
+        assert!(counts[1..].iter().all(|&count| count < counts[0]));
+    }
+
+    #[test]
+    fn specks_stay_near_their_cell() {
+        let seed = RunSeed(5);
+        for (layer, settings) in LAYERS.iter().enumerate() {
+            for x in -20..20 {
+                let tile = IVec2::new(x, 7);
+                let Some((pos, _, _)) = speck_look(seed, layer, tile, settings.spacing) else {
+                    continue;
+                };
+                let offset = pos - tile.as_vec2() * settings.spacing;
+                assert!(offset.abs().max_element() <= settings.spacing / 2.0);
+            }
         }
     }
 }
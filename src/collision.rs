@@ -0,0 +1,421 @@
+use std::ops::BitOr;
+
+use bevy::transform::TransformSystem;
+use bevy::utils::{HashMap, HashSet};
+
+use crate::prelude::*;
+use crate::world_running;
+
+/// Size of the spatial hash cells, roughly the size of the bigger colliders
+const CELL_SIZE: f32 = 64.0;
+
+pub struct CollisionPlugin;
+
+impl Plugin for CollisionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SpatialHash>()
+            .add_event::<Collision>()
+            .add_systems(
+                PostUpdate,
+                (rebuild_spatial_hash, detect_collisions)
+                    .chain()
+                    .after(TransformSystem::TransformPropagate)
+                    .run_if(world_running),
+            );
+
+        #[cfg(feature = "dev")]
+        app.add_systems(Update, draw_colliders.run_if(world_running));
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Hash)]
+pub struct Layers(u8);
+
+impl Layers {
+    pub const PLAYER: Self = Self(1 << 0);
+    pub const ENEMY: Self = Self(1 << 1);
+    pub const PLAYER_BULLET: Self = Self(1 << 2);
+    pub const ENEMY_BULLET: Self = Self(1 << 3);
+    pub const PICKUP: Self = Self(1 << 4);
+    pub const WALL: Self = Self(1 << 5);
+
+    pub const fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl BitOr for Layers {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shape {
+    Circle { radius: f32 },
+    Aabb { half_size: Vec2 },
+}
+
+impl Shape {
+    const fn half_extents(self) -> Vec2 {
+        match self {
+            Self::Circle { radius } => Vec2::splat(radius),
+            Self::Aabb { half_size } => half_size,
+        }
+    }
+
+    fn overlaps(self, pos: Vec2, other: Self, other_pos: Vec2) -> bool {
+        match (self, other) {
+            (
+                Self::Circle { radius },
+                Self::Circle {
+                    radius: other_radius,
+                },
+            ) => pos.distance_squared(other_pos) <= (radius + other_radius).powi(2),
+            (
+                Self::Aabb { half_size },
+                Self::Aabb {
+                    half_size: other_half_size,
+                },
+            ) => {
+                let delta = (pos - other_pos).abs();
+                delta.x <= half_size.x + other_half_size.x
+                    && delta.y <= half_size.y + other_half_size.y
+            }
+            (Self::Circle { radius }, Self::Aabb { half_size }) => {
+                circle_overlaps_aabb(pos, radius, other_pos, half_size)
+            }
+            (Self::Aabb { half_size }, Self::Circle { radius }) => {
+                circle_overlaps_aabb(other_pos, radius, pos, half_size)
+            }
+        }
+    }
+
+    /// How far this shape has to move to stop overlapping the other one, zero if they don't
+    pub fn separation(self, pos: Vec2, other: Self, other_pos: Vec2) -> Vec2 {
+        match (self, other) {
+            (
+                Self::Circle { radius },
+                Self::Circle {
+                    radius: other_radius,
+                },
+            ) => {
+                let delta = pos - other_pos;
+                let overlap = radius + other_radius - delta.length();
+                if overlap <= 0.0 {
+                    return Vec2::ZERO;
+                }
+                delta.try_normalize().unwrap_or(Vec2::X) * overlap
+            }
+            (
+                Self::Aabb { half_size },
+                Self::Aabb {
+                    half_size: other_half_size,
+                },
+            ) => {
+                let delta = pos - other_pos;
+                let overlap = half_size + other_half_size - delta.abs();
+                if overlap.x <= 0.0 || overlap.y <= 0.0 {
+                    Vec2::ZERO
+                } else if overlap.x < overlap.y {
+                    Vec2::new(overlap.x.copysign(delta.x), 0.0)
+                } else {
+                    Vec2::new(0.0, overlap.y.copysign(delta.y))
+                }
+            }
+            (Self::Circle { radius }, Self::Aabb { half_size }) => {
+                circle_out_of_aabb(pos, radius, other_pos, half_size)
+            }
+            (Self::Aabb { .. }, Self::Circle { .. }) => -other.separation(other_pos, self, pos),
+        }
+    }
+}
+
+fn circle_out_of_aabb(center: Vec2, radius: f32, box_center: Vec2, half_size: Vec2) -> Vec2 {
+    let closest = center.clamp(box_center - half_size, box_center + half_size);
+    let offset = center - closest;
+    if offset == Vec2::ZERO {
+        // The center is inside the box, so get out through the nearest side
+        let square = Shape::Aabb {
+            half_size: Vec2::splat(radius),
+        };
+        return square.separation(center, Shape::Aabb { half_size }, box_center);
+    }
+    let distance = offset.length();
+    if distance >= radius {
+        return Vec2::ZERO;
+    }
+    offset / distance * (radius - distance)
+}
+
+fn circle_overlaps_aabb(center: Vec2, radius: f32, box_center: Vec2, half_size: Vec2) -> bool {
+    let closest = center.clamp(box_center - half_size, box_center + half_size);
+    center.distance_squared(closest) <= radius * radius
+}
+
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Collider {
+    pub shape: Shape,
+    /// The layers this collider is on
+    pub layer: Layers,
+    /// The layers this collider wants to hear about colliding with
+    pub mask: Layers,
+}
+
+impl Collider {
+    pub const fn circle(radius: f32, layer: Layers, mask: Layers) -> Self {
+        Self {
+            shape: Shape::Circle { radius },
+            layer,
+            mask,
+        }
+    }
+
+    pub const fn aabb(half_size: Vec2, layer: Layers, mask: Layers) -> Self {
+        Self {
+            shape: Shape::Aabb { half_size },
+            layer,
+            mask,
+        }
+    }
+
+    const fn interacts_with(&self, other: &Self) -> bool {
+        self.mask.intersects(other.layer) || other.mask.intersects(self.layer)
+    }
+}
+
+/// Sent once per overlapping pair every frame they overlap
+#[derive(Event, Debug, Clone, Copy)]
+pub struct Collision {
+    pub a: Entity,
+    pub a_layer: Layers,
+    pub b: Entity,
+    pub b_layer: Layers,
+}
+
+impl Collision {
+    /// The entities of this collision if it is between the two layers, in that order
+    pub const fn between(&self, first: Layers, second: Layers) -> Option<(Entity, Entity)> {
+        if self.a_layer.intersects(first) && self.b_layer.intersects(second) {
+            Some((self.a, self.b))
+        } else if self.b_layer.intersects(first) && self.a_layer.intersects(second) {
+            Some((self.b, self.a))
+        } else {
+            None
+        }
+    }
+}
+
+struct Entry {
+    entity: Entity,
+    pos: Vec2,
+    collider: Collider,
+}
+
+/// Uniform grid of every collider, rebuilt every frame
+#[derive(Resource, Default)]
+pub struct SpatialHash {
+    entries: Vec<Entry>,
+    cells: HashMap<IVec2, Vec<usize>>,
+}
+
+fn cells_covering(pos: Vec2, half_extents: Vec2) -> impl Iterator<Item = IVec2> {
+    let min = ((pos - half_extents) / CELL_SIZE).floor().as_ivec2();
+    let max = ((pos + half_extents) / CELL_SIZE).floor().as_ivec2();
+    (min.x..=max.x).flat_map(move |x| (min.y..=max.y).map(move |y| IVec2::new(x, y)))
+}
+
+impl SpatialHash {
+    fn clear(&mut self) {
+        self.entries.clear();
+        // Keep the allocations of cells used last frame around, the same cells tend to be used
+        // frame to frame, but forget the rest so the map doesn't grow with every cell ever visited
+        self.cells.retain(|_, cell| !cell.is_empty());
+        for cell in self.cells.values_mut() {
+            cell.clear();
+        }
+    }
+
+    fn insert(&mut self, entity: Entity, pos: Vec2, collider: Collider) {
+        let index = self.entries.len();
+        self.entries.push(Entry {
+            entity,
+            pos,
+            collider,
+        });
+        for cell in cells_covering(pos, collider.shape.half_extents()) {
+            self.cells.entry(cell).or_default().push(index);
+        }
+    }
+
+    fn collisions(&self) -> Vec<Collision> {
+        let mut seen = HashSet::new();
+        let mut collisions = Vec::new();
+        for indices in self.cells.values() {
+            for (i, &first) in indices.iter().enumerate() {
+                for &second in &indices[i + 1..] {
+                    let (a, b) = (&self.entries[first], &self.entries[second]);
+                    if !a.collider.interacts_with(&b.collider)
+                        || !a.collider.shape.overlaps(a.pos, b.collider.shape, b.pos)
+                        || !seen.insert((first.min(second), first.max(second)))
+                    {
+                        continue;
+                    }
+                    collisions.push(Collision {
+                        a: a.entity,
+                        a_layer: a.collider.layer,
+                        b: b.entity,
+                        b_layer: b.collider.layer,
+                    });
+                }
+            }
+        }
+        collisions
+    }
+}
+
+fn rebuild_spatial_hash(
+    mut hash: ResMut<SpatialHash>,
+    colliders: Query<(Entity, &GlobalTransform, &Collider, Option<&Visibility>)>,
+) {
+    hash.clear();
+    for (entity, trans, collider, visibility) in &colliders {
+        // Hidden entities are parked in a pool and shouldn't hit anything
+        if visibility == Some(&Visibility::Hidden) {
+            continue;
+        }
+        hash.insert(entity, trans.translation().truncate(), *collider);
+    }
+}
+
+fn detect_collisions(hash: Res<SpatialHash>, mut events: EventWriter<Collision>) {
+    events.send_batch(hash.collisions());
+}
+
+#[cfg(feature = "dev")]
+fn draw_colliders(mut gizmos: Gizmos, colliders: Query<(&GlobalTransform, &Collider)>) {
+    for (trans, collider) in &colliders {
+        let pos = trans.translation().truncate();
+        match collider.shape {
+            Shape::Circle { radius } => {
+                gizmos.circle_2d(pos, radius, Color::RED);
+            }
+            Shape::Aabb { half_size } => {
+                gizmos.rect_2d(pos, 0.0, half_size * 2.0, Color::RED);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash_with(colliders: &[(Vec2, Collider)]) -> (SpatialHash, Vec<Entity>) {
+        let mut world = World::new();
+        let mut hash = SpatialHash::default();
+        let entities = colliders
+            .iter()
+            .map(|(pos, collider)| {
+                let entity = world.spawn_empty().id();
+                hash.insert(entity, *pos, *collider);
+                entity
+            })
+            .collect();
+        (hash, entities)
+    }
+
+    #[test]
+    fn shapes_overlap() {
+        let circle = Shape::Circle { radius: 10.0 };
+        let square = Shape::Aabb {
+            half_size: Vec2::splat(10.0),
+        };
+
+        assert!(circle.overlaps(Vec2::ZERO, circle, Vec2::new(19.0, 0.0)));
+        assert!(!circle.overlaps(Vec2::ZERO, circle, Vec2::new(21.0, 0.0)));
+        assert!(square.overlaps(Vec2::ZERO, square, Vec2::new(19.0, 19.0)));
+        assert!(!square.overlaps(Vec2::ZERO, square, Vec2::new(19.0, 21.0)));
+        // The corner of the box is further away than its sides
+        assert!(circle.overlaps(Vec2::ZERO, square, Vec2::new(19.0, 0.0)));
+        assert!(!square.overlaps(Vec2::new(18.0, 18.0), circle, Vec2::ZERO));
+    }
+
+    #[test]
+    fn collisions_respect_layers() {
+        let bullet = Collider::circle(5.0, Layers::PLAYER_BULLET, Layers::ENEMY);
+        let enemy = Collider::circle(20.0, Layers::ENEMY, Layers::PLAYER);
+        let pickup = Collider::circle(5.0, Layers::PICKUP, Layers::PLAYER);
+        let (hash, entities) = hash_with(&[
+            (Vec2::ZERO, bullet),
+            (Vec2::new(10.0, 0.0), enemy),
+            (Vec2::new(5.0, 0.0), pickup),
+        ]);
+
+        let collisions = hash.collisions();
+        assert_eq!(collisions.len(), 1);
+        assert_eq!(
+            collisions[0].between(Layers::PLAYER_BULLET, Layers::ENEMY),
+            Some((entities[0], entities[1]))
+        );
+        assert_eq!(
+            collisions[0].between(Layers::ENEMY, Layers::PLAYER_BULLET),
+            Some((entities[1], entities[0]))
+        );
+        assert_eq!(collisions[0].between(Layers::PLAYER, Layers::ENEMY), None);
+    }
+
+    #[test]
+    fn pairs_spanning_cells_are_reported_once() {
+        let big = Collider::aabb(Vec2::splat(CELL_SIZE * 2.0), Layers::ENEMY, Layers::PLAYER);
+        let player = Collider::circle(CELL_SIZE, Layers::PLAYER, Layers::default());
+        let (hash, _) = hash_with(&[(Vec2::ZERO, big), (Vec2::splat(CELL_SIZE), player)]);
+
+        assert_eq!(hash.collisions().len(), 1);
+    }
+
+    #[test]
+    fn unused_cells_are_forgotten() {
+        let collider = Collider::circle(5.0, Layers::PLAYER, Layers::ENEMY);
+        let mut hash = SpatialHash::default();
+        for step in 0..100 {
+            hash.clear();
+            let pos = Vec2::new(step as f32 * 3.0 + 0.5, 0.5) * CELL_SIZE;
+            hash.insert(Entity::from_raw(0), pos, collider);
+        }
+        // The cells from this frame and the one before, not every cell ever visited
+        assert!(hash.cells.len() <= 2);
+    }
+
+    #[test]
+    fn shapes_separate() {
+        let circle = Shape::Circle { radius: 10.0 };
+        let square = Shape::Aabb {
+            half_size: Vec2::splat(10.0),
+        };
+
+        assert_eq!(
+            square.separation(Vec2::new(15.0, 2.0), square, Vec2::ZERO),
+            Vec2::new(5.0, 0.0)
+        );
+        assert_eq!(
+            circle.separation(Vec2::new(0.0, -15.0), square, Vec2::ZERO),
+            Vec2::new(0.0, -5.0)
+        );
+        // Deep inside the box still gets out the closest way
+        assert_eq!(
+            circle.separation(Vec2::new(-8.0, 0.0), square, Vec2::ZERO),
+            Vec2::new(-12.0, 0.0)
+        );
+        assert_eq!(
+            square.separation(Vec2::ZERO, circle, Vec2::new(0.0, -15.0)),
+            Vec2::new(0.0, 5.0)
+        );
+        assert_eq!(
+            circle.separation(Vec2::ZERO, circle, Vec2::new(30.0, 0.0)),
+            Vec2::ZERO
+        );
+    }
+}
@@ -0,0 +1,502 @@
+//! Animation controllers, a set of named clips with transitions between them picked by what the
+//! entity is doing, and events sent when specific frames start.
+//!
+//! What an entity animates like is plain data, an `AnimationDef`, the clips pull their frames
+//! out of an Aseprite file by tag.
+
+use std::time::Duration;
+
+use super::feedback::FeedbackSettings;
+use super::{Health, MovingDirection, Player};
+use crate::assets::Aseprite;
+use crate::prelude::*;
+use crate::world_running;
+
+const FLASH_COLOR: Color = Color::rgb(1.0, 0.3, 0.3);
+
+pub struct AnimationPlugin;
+
+impl Plugin for AnimationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<AnimationEvent>().add_systems(
+            Update,
+            ((cue_moving, cue_hurt), run_animations, flash_on_events)
+                .chain()
+                .run_if(world_running),
+        );
+    }
+}
+
+/// Where the frames of a clip come from
+pub(super) enum Frames {
+    /// An Aseprite tag
+    Tag(&'static str),
+    /// Every frame of the file
+    All,
+    /// Frames that only time events and leave the sprite alone, in milliseconds
+    Timed(&'static [u64]),
+}
+
+pub(super) struct ClipDef {
+    pub name: &'static str,
+    pub frames: Frames,
+    /// Multiplies the playback speed of the frames
+    pub speed: f32,
+    pub looping: bool,
+    /// Events sent when the frame at the index starts
+    pub events: &'static [(usize, &'static str)],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Condition {
+    Moving,
+    Still,
+    Hurt,
+    Shooting,
+    /// A clip that doesn't loop got to its end
+    Finished,
+}
+
+pub(super) struct TransitionDef {
+    /// Clips this can happen from, empty for any
+    pub from: &'static [&'static str],
+    pub to: &'static str,
+    pub when: Condition,
+}
+
+pub(super) struct AnimationDef {
+    pub start: &'static str,
+    pub clips: &'static [ClipDef],
+    /// Checked in order, the first that applies is taken
+    pub transitions: &'static [TransitionDef],
+}
+
+pub(super) const PLAYER_ANIMATIONS: AnimationDef = AnimationDef {
+    start: "idle",
+    clips: &[
+        ClipDef {
+            name: "idle",
+            frames: Frames::Tag("Idle"),
+            speed: 1.0,
+            looping: true,
+            events: &[],
+        },
+        ClipDef {
+            name: "moving",
+            frames: Frames::Tag("Moving"),
+            speed: 1.0,
+            looping: true,
+            events: &[],
+        },
+        ClipDef {
+            name: "shoot",
+            frames: Frames::Tag("Moving"),
+            speed: 3.0,
+            looping: false,
+            events: &[],
+        },
+        ClipDef {
+            name: "hurt",
+            frames: Frames::Timed(&[80, 80, 80, 80]),
+            speed: 1.0,
+            looping: false,
+            events: &[
+                (0, "flash_on"),
+                (1, "flash_off"),
+                (2, "flash_on"),
+                (3, "flash_off"),
+            ],
+        },
+    ],
+    transitions: &[
+        TransitionDef {
+            from: &[],
+            to: "hurt",
+            when: Condition::Hurt,
+        },
+        TransitionDef {
+            from: &["idle", "moving"],
+            to: "shoot",
+            when: Condition::Shooting,
+        },
+        TransitionDef {
+            from: &["hurt", "shoot"],
+            to: "idle",
+            when: Condition::Finished,
+        },
+        TransitionDef {
+            from: &["idle"],
+            to: "moving",
+            when: Condition::Moving,
+        },
+        TransitionDef {
+            from: &["moving"],
+            to: "idle",
+            when: Condition::Still,
+        },
+    ],
+};
+
+pub(super) const BULLET_ANIMATIONS: AnimationDef = AnimationDef {
+    start: "fly",
+    clips: &[ClipDef {
+        name: "fly",
+        frames: Frames::All,
+        speed: 1.0,
+        looping: true,
+        events: &[],
+    }],
+    transitions: &[],
+};
+
+/// Enemies are shapes, so their clips only time the hit flash
+pub(super) const ENEMY_ANIMATIONS: AnimationDef = AnimationDef {
+    start: "idle",
+    clips: &[
+        ClipDef {
+            name: "idle",
+            frames: Frames::Timed(&[1000]),
+            speed: 1.0,
+            looping: true,
+            events: &[],
+        },
+        ClipDef {
+            name: "hurt",
+            frames: Frames::Timed(&[60, 60]),
+            speed: 1.0,
+            looping: false,
+            events: &[(0, "flash_on"), (1, "flash_off")],
+        },
+    ],
+    transitions: &[
+        TransitionDef {
+            from: &[],
+            to: "hurt",
+            when: Condition::Hurt,
+        },
+        TransitionDef {
+            from: &["hurt"],
+            to: "idle",
+            when: Condition::Finished,
+        },
+    ],
+};
+
+/// Sent when a frame with an event in its clip starts
+#[derive(Event, Debug, Clone, Copy)]
+pub(super) struct AnimationEvent {
+    pub entity: Entity,
+    pub name: &'static str,
+}
+
+/// What the entity is doing, set by gameplay and read by the transitions
+#[derive(Component, Default, Debug)]
+pub(super) struct AnimationCues {
+    pub moving: bool,
+    /// Only count for the frame they are set in
+    pub hurt: bool,
+    pub shooting: bool,
+}
+
+struct ClipFrame {
+    /// `None` leaves the atlas where it is
+    index: Option<usize>,
+    duration: Duration,
+}
+
+#[derive(Component)]
+pub(super) struct AnimationController {
+    def: &'static AnimationDef,
+    /// The frames of each clip of the def
+    clips: Vec<Vec<ClipFrame>>,
+    current: usize,
+    frame: usize,
+    timer: Timer,
+    finished: bool,
+}
+
+impl AnimationController {
+    pub fn new(def: &'static AnimationDef, sheet: Option<&Aseprite>) -> Self {
+        let clips = def
+            .clips
+            .iter()
+            .map(|clip| {
+                let frames = match (&clip.frames, sheet) {
+                    (Frames::Tag(tag), Some(sheet)) => sheet.clip(tag).unwrap_or_else(|| {
+                        bevy::log::warn!("Animation tag {tag} is missing");
+                        Vec::new()
+                    }),
+                    (Frames::All, Some(sheet)) => sheet.all_frames(),
+                    (Frames::Timed(millis), _) => {
+                        return millis
+                            .iter()
+                            .map(|&millis| ClipFrame {
+                                index: None,
+                                duration: Duration::from_millis(millis).div_f32(clip.speed),
+                            })
+                            .collect();
+                    }
+                    (_, None) => Vec::new(),
+                };
+                frames
+                    .into_iter()
+                    .map(|frame| ClipFrame {
+                        index: Some(frame.index),
+                        duration: frame.duration.div_f32(clip.speed),
+                    })
+                    .collect()
+            })
+            .collect();
+        let mut controller = Self {
+            def,
+            clips,
+            current: 0,
+            frame: 0,
+            timer: Timer::default(),
+            finished: false,
+        };
+        controller.start(controller.clip_index(def.start).unwrap_or_default());
+        controller
+    }
+
+    fn clip_index(&self, name: &str) -> Option<usize> {
+        self.def.clips.iter().position(|clip| clip.name == name)
+    }
+
+    pub fn clip(&self) -> &'static str {
+        self.def.clips[self.current].name
+    }
+
+    fn start(&mut self, clip: usize) {
+        self.current = clip;
+        self.finished = false;
+        self.set_frame(0);
+    }
+
+    fn set_frame(&mut self, frame: usize) {
+        self.frame = frame;
+        let duration = self.clips[self.current]
+            .get(frame)
+            .map_or(Duration::ZERO, |frame| frame.duration);
+        self.timer = Timer::new(duration, TimerMode::Once);
+    }
+
+    /// Names of the events on the current frame
+    fn events(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.def.clips[self.current]
+            .events
+            .iter()
+            .filter(|(frame, _)| *frame == self.frame)
+            .map(|(_, name)| *name)
+    }
+
+    const fn holds(&self, condition: Condition, cues: &AnimationCues) -> bool {
+        match condition {
+            Condition::Moving => cues.moving,
+            Condition::Still => !cues.moving,
+            Condition::Hurt => cues.hurt,
+            Condition::Shooting => cues.shooting,
+            Condition::Finished => self.finished,
+        }
+    }
+
+    /// Takes the first transition that applies, returns if one was taken
+    fn transition(&mut self, cues: &AnimationCues) -> bool {
+        let current = self.clip();
+        let next = self.def.transitions.iter().find(|transition| {
+            transition.to != current
+                && (transition.from.is_empty() || transition.from.contains(&current))
+                && self.holds(transition.when, cues)
+        });
+        let Some(clip) = next.and_then(|transition| self.clip_index(transition.to)) else {
+            return false;
+        };
+        self.start(clip);
+        true
+    }
+
+    /// Moves time forward, returns if a new frame started
+    fn advance(&mut self, delta: Duration) -> bool {
+        let frames = self.clips[self.current].len();
+        if self.finished || frames == 0 || !self.timer.tick(delta).finished() {
+            return false;
+        }
+        if self.frame + 1 < frames {
+            self.set_frame(self.frame + 1);
+        } else if self.def.clips[self.current].looping {
+            self.set_frame(0);
+        } else {
+            self.finished = true;
+            return false;
+        }
+        true
+    }
+
+    fn atlas_index(&self) -> Option<usize> {
+        self.clips[self.current]
+            .get(self.frame)
+            .and_then(|frame| frame.index)
+    }
+}
+
+fn cue_moving(mut query: Query<(&MovingDirection, &mut AnimationCues), With<Player>>) {
+    for (moving, mut cues) in &mut query {
+        cues.moving = moving.0 != Vec2::ZERO;
+    }
+}
+
+fn cue_hurt(mut query: Query<(Ref<Health>, &mut AnimationCues)>) {
+    for (health, mut cues) in &mut query {
+        if health.is_changed() && !health.is_added() {
+            cues.hurt = true;
+        }
+    }
+}
+
+fn run_animations(
+    mut query: Query<(
+        Entity,
+        &mut AnimationController,
+        Option<&mut AnimationCues>,
+        Option<&mut TextureAtlas>,
+    )>,
+    mut events: EventWriter<AnimationEvent>,
+    time: Res<Time>,
+) {
+    for (entity, mut controller, cues, atlas) in &mut query {
+        let mut new_frame = controller.advance(time.delta());
+        if let Some(mut cues) = cues {
+            new_frame |= controller.transition(&cues);
+            cues.hurt = false;
+            cues.shooting = false;
+        }
+        if new_frame {
+            events.send_batch(
+                controller
+                    .events()
+                    .map(|name| AnimationEvent { entity, name }),
+            );
+        }
+        if let (Some(mut atlas), Some(index)) = (atlas, controller.atlas_index()) {
+            if atlas.index != index {
+                atlas.index = index;
+            }
+        }
+    }
+}
+
+/// The colors from before a flash
+#[derive(Component)]
+struct Flashed {
+    sprite: Option<Color>,
+    stroke: Option<Color>,
+    fill: Option<Color>,
+}
+
+fn flash_on_events(
+    mut commands: Commands,
+    mut events: EventReader<AnimationEvent>,
+    mut targets: Query<(
+        Option<&mut Sprite>,
+        Option<&mut Stroke>,
+        Option<&mut Fill>,
+        Option<&Flashed>,
+    )>,
+    settings: Res<FeedbackSettings>,
+) {
+    for event in events.read() {
+        let Ok((sprite, stroke, fill, flashed)) = targets.get_mut(event.entity) else {
+            continue;
+        };
+        match (event.name, flashed) {
+            ("flash_on", None) if settings.flash => {
+                let mut before = Flashed {
+                    sprite: None,
+                    stroke: None,
+                    fill: None,
+                };
+                if let Some(mut sprite) = sprite {
+                    before.sprite = Some(sprite.color);
+                    sprite.color = FLASH_COLOR;
+                }
+                if let Some(mut stroke) = stroke {
+                    before.stroke = Some(stroke.color);
+                    stroke.color = FLASH_COLOR;
+                }
+                if let Some(mut fill) = fill {
+                    before.fill = Some(fill.color);
+                    fill.color = FLASH_COLOR;
+                }
+                commands.entity(event.entity).insert(before);
+            }
+            ("flash_off", Some(before)) => {
+                if let (Some(mut sprite), Some(color)) = (sprite, before.sprite) {
+                    sprite.color = color;
+                }
+                if let (Some(mut stroke), Some(color)) = (stroke, before.stroke) {
+                    stroke.color = color;
+                }
+                if let (Some(mut fill), Some(color)) = (fill, before.fill) {
+                    // Chargers recolor themselves for their windup, that wins over the flash
+                    if fill.color == FLASH_COLOR {
+                        fill.color = color;
+                    }
+                }
+                commands.entity(event.entity).remove::<Flashed>();
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cued(moving: bool, hurt: bool) -> AnimationCues {
+        AnimationCues {
+            moving,
+            hurt,
+            shooting: false,
+        }
+    }
+
+    #[test]
+    fn every_transition_names_real_clips() {
+        for def in [&PLAYER_ANIMATIONS, &BULLET_ANIMATIONS, &ENEMY_ANIMATIONS] {
+            let names: Vec<_> = def.clips.iter().map(|clip| clip.name).collect();
+            assert!(names.contains(&def.start));
+            for transition in def.transitions {
+                assert!(names.contains(&transition.to), "{}", transition.to);
+                assert!(transition.from.iter().all(|from| names.contains(from)));
+            }
+        }
+    }
+
+    #[test]
+    fn hurt_plays_once_then_goes_back() {
+        let mut controller = AnimationController::new(&ENEMY_ANIMATIONS, None);
+        assert_eq!(controller.clip(), "idle");
+
+        assert!(controller.transition(&cued(false, true)));
+        assert_eq!(controller.clip(), "hurt");
+        assert_eq!(controller.events().collect::<Vec<_>>(), ["flash_on"]);
+
+        assert!(controller.advance(Duration::from_millis(60)));
+        assert_eq!(controller.events().collect::<Vec<_>>(), ["flash_off"]);
+        assert!(!controller.advance(Duration::from_millis(60)));
+
+        assert!(controller.transition(&cued(false, false)));
+        assert_eq!(controller.clip(), "idle");
+    }
+
+    #[test]
+    fn moving_switches_clips() {
+        let mut controller = AnimationController::new(&PLAYER_ANIMATIONS, None);
+        assert!(!controller.transition(&cued(false, false)));
+        assert!(controller.transition(&cued(true, false)));
+        assert_eq!(controller.clip(), "moving");
+        assert!(controller.transition(&cued(false, false)));
+        assert_eq!(controller.clip(), "idle");
+    }
+}
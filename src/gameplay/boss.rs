@@ -0,0 +1,308 @@
+//! Boss fights, a tough enemy switching attack patterns as it loses health while the player is
+//! locked in an arena with it.
+
+use super::bullet_pool::BulletPool;
+use super::enemies::{spawn_enemy, EnemyKind};
+use super::{Health, Player, SourceNode};
+use crate::node_editor::{EnemyPatterns, GraphOwner};
+use crate::prelude::*;
+use crate::{world_running, MainState, ZIndex};
+
+/// Seconds of fighting between bosses
+const BOSS_INTERVAL: f32 = 90.0;
+const BOSS_NAME: &str = "The Warden";
+const BOSS_DISTANCE: f32 = 400.0;
+const ARENA_RADIUS: f32 = 700.0;
+
+struct Phase {
+    /// What the pattern is called in the editor
+    name: &'static str,
+    /// Health fraction at or below which this phase starts
+    below: f32,
+    /// Default pattern, can be changed in the editor through [`EnemyPatterns`]
+    pattern: &'static str,
+}
+
+const PHASES: [Phase; 3] = [
+    Phase {
+        name: "Warden phase 1",
+        below: 1.0,
+        pattern: "on_shoot -> spread(3, 40deg) -> spread(3, 15deg) -> spawn_bullet.hit -> deal_dmg",
+    },
+    Phase {
+        name: "Warden phase 2",
+        below: 0.66,
+        pattern: "on_shoot -> spread(16, 337.5deg) -> spawn_bullet.hit -> deal_dmg",
+    },
+    Phase {
+        name: "Warden phase 3",
+        below: 0.33,
+        pattern: "
+            on_shoot -> spread(8, 315deg) -> bullet: spawn_bullet.hit -> deal_dmg
+            bullet.despawned -> spread(3, 0deg) -> spawn_bullet.hit -> deal_dmg
+        ",
+    },
+];
+
+pub struct BossPlugin;
+
+impl Plugin for BossPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BossTimer>()
+            .add_systems(Startup, register_patterns)
+            .add_systems(OnEnter(MainState::Playing), reset_boss_timer)
+            .add_systems(
+                Update,
+                (
+                    start_fight.run_if(not(resource_exists::<ArenaLock>)),
+                    (
+                        // Parks bullets, that has to land before hits and timeouts park them too
+                        change_phase.before(super::bullet_hits),
+                        update_boss_bar,
+                        end_fight,
+                    )
+                        .run_if(resource_exists::<ArenaLock>),
+                )
+                    .run_if(world_running),
+            );
+    }
+}
+
+/// Keeps the player inside a circle while a boss fight is going on
+#[derive(Resource, Debug)]
+pub struct ArenaLock {
+    center: Vec2,
+    radius: f32,
+}
+
+#[derive(Component)]
+pub(super) struct Boss {
+    phase: usize,
+}
+
+/// Everything that only exists during the fight, cleaned up when it ends
+#[derive(Component)]
+struct FightOnly;
+
+#[derive(Component)]
+struct BossBarFill;
+
+#[derive(Resource)]
+pub(super) struct BossTimer(pub(super) Timer);
+
+impl Default for BossTimer {
+    fn default() -> Self {
+        Self(Timer::from_seconds(BOSS_INTERVAL, TimerMode::Repeating))
+    }
+}
+
+fn reset_boss_timer(mut commands: Commands) {
+    commands.insert_resource(BossTimer::default());
+    commands.remove_resource::<ArenaLock>();
+}
+
+fn register_patterns(mut patterns: ResMut<EnemyPatterns>) {
+    for phase in &PHASES {
+        patterns.register(phase.name, phase.pattern);
+    }
+}
+
+fn start_fight(
+    mut commands: Commands,
+    mut timer: ResMut<BossTimer>,
+    patterns: Res<EnemyPatterns>,
+    player: Query<&Transform, With<Player>>,
+    time: Res<Time>,
+) {
+    if !timer.0.tick(time.delta()).just_finished() {
+        return;
+    }
+    let Ok(player) = player.get_single() else {
+        return;
+    };
+    let center = player.translation.truncate();
+
+    let boss = spawn_enemy(
+        &mut commands,
+        &patterns,
+        EnemyKind::Warden,
+        center + Vec2::Y * BOSS_DISTANCE,
+    );
+    commands.entity(boss).insert(Boss { phase: 0 });
+    if let Some(graph) = patterns.graph(PHASES[0].name) {
+        commands.entity(boss).insert(graph);
+    }
+
+    commands.insert_resource(ArenaLock {
+        center,
+        radius: ARENA_RADIUS,
+    });
+    commands.spawn((
+        Gc(MainState::Playing),
+        FightOnly,
+        ShapeBundle {
+            path: GeometryBuilder::build_as(&shapes::Circle {
+                radius: ARENA_RADIUS,
+                center: Vec2::ZERO,
+            }),
+            spatial: SpatialBundle::from_transform(Transform::from_translation(
+                center.extend(ZIndex::Enemy.into()),
+            )),
+            ..default()
+        },
+        Stroke::new(Color::rgb(0.6, 0.1, 0.1), 8.0),
+        Name::new("Arena"),
+    ));
+    spawn_boss_bar(&mut commands);
+}
+
+fn spawn_boss_bar(commands: &mut Commands) {
+    commands
+        .spawn((
+            Gc(MainState::Playing),
+            FightOnly,
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Px(16.0),
+                    left: Val::Percent(25.0),
+                    width: Val::Percent(50.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    row_gap: Val::Px(4.0),
+                    ..default()
+                },
+                ..default()
+            },
+            Name::new("Boss Bar"),
+        ))
+        .with_children(|bar| {
+            bar.spawn(TextBundle::from_section(
+                BOSS_NAME,
+                TextStyle {
+                    font_size: 24.0,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            ));
+            bar.spawn(NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Px(16.0),
+                    ..default()
+                },
+                background_color: Color::rgb(0.2, 0.05, 0.05).into(),
+                ..default()
+            })
+            .with_children(|background| {
+                background.spawn((
+                    BossBarFill,
+                    NodeBundle {
+                        style: Style {
+                            width: Val::Percent(100.0),
+                            height: Val::Percent(100.0),
+                            ..default()
+                        },
+                        background_color: Color::RED.into(),
+                        ..default()
+                    },
+                ));
+            });
+        });
+}
+
+/// Which phase a boss should be in at the given health fraction
+fn phase_for(fraction: f32) -> usize {
+    PHASES
+        .iter()
+        .rposition(|phase| fraction <= phase.below)
+        .unwrap_or_default()
+}
+
+fn change_phase(
+    mut commands: Commands,
+    mut bosses: Query<(Entity, &mut Boss, &Health), Changed<Health>>,
+    bullets: Query<(Entity, &SourceNode, &Visibility)>,
+    patterns: Res<EnemyPatterns>,
+    mut pool: ResMut<BulletPool>,
+) {
+    for (entity, mut boss, health) in &mut bosses {
+        let phase = phase_for(health.current / health.max);
+        if phase <= boss.phase {
+            continue;
+        }
+        boss.phase = phase;
+        // Bullets from the old pattern point at its nodes, in the new graph those ids are
+        // something else entirely, so they go away with the pattern that fired them
+        for (bullet, source, visibility) in &bullets {
+            if source.owner == GraphOwner::Entity(entity) && visibility != Visibility::Hidden {
+                pool.park(&mut commands, bullet);
+            }
+        }
+        if let Some(graph) = patterns.graph(PHASES[phase].name) {
+            commands.entity(entity).insert(graph);
+        }
+    }
+}
+
+fn update_boss_bar(
+    bosses: Query<&Health, With<Boss>>,
+    mut fills: Query<&mut Style, With<BossBarFill>>,
+) {
+    let Ok(health) = bosses.get_single() else {
+        return;
+    };
+    for mut style in &mut fills {
+        style.width = Val::Percent(health.current / health.max * 100.0);
+    }
+}
+
+fn end_fight(
+    mut commands: Commands,
+    bosses: Query<(), With<Boss>>,
+    fight_only: Query<Entity, With<FightOnly>>,
+) {
+    if !bosses.is_empty() {
+        return;
+    }
+    commands.remove_resource::<ArenaLock>();
+    for entity in &fight_only {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+pub(super) fn keep_in_arena(
+    mut player: Query<&mut Transform, With<Player>>,
+    arena: Option<Res<ArenaLock>>,
+) {
+    let Some(arena) = arena else {
+        return;
+    };
+    let Ok(mut trans) = player.get_single_mut() else {
+        return;
+    };
+    let offset = trans.translation.truncate() - arena.center;
+    let clamped = arena.center + offset.clamp_length_max(arena.radius);
+    trans.translation = clamped.extend(trans.translation.z);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node_editor::NodeGraph;
+
+    #[test]
+    fn every_phase_pattern_parses() {
+        for phase in &PHASES {
+            let parsed = NodeGraph::from_dsl(phase.pattern).map(|_| ());
+            assert_eq!(parsed, Ok(()), "{}", phase.pattern);
+        }
+    }
+
+    #[test]
+    fn phases_follow_health() {
+        assert_eq!(phase_for(1.0), 0);
+        assert_eq!(phase_for(0.5), 1);
+        assert_eq!(phase_for(0.1), 2);
+    }
+}
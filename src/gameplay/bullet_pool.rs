@@ -0,0 +1,84 @@
+use bevy::ecs::entity::EntityHashSet;
+
+use crate::prelude::*;
+
+/// How many parked bullets we keep around, anything over this is despawned for real
+const MAX_PARKED: usize = 2048;
+
+/// Bullets are never despawned, they are hidden and parked here to be reused by the next shot
+#[derive(Resource, Default, Debug)]
+pub struct BulletPool {
+    parked: Vec<Entity>,
+    /// Same bullets as `parked`, so parking one twice in a frame doesn't hand it out twice
+    is_parked: EntityHashSet,
+    active: usize,
+    spawned: usize,
+    reused: usize,
+}
+
+impl BulletPool {
+    /// A parked bullet to reuse, the caller has to reset all of its components
+    pub fn take(&mut self) -> Option<Entity> {
+        let entity = self.parked.pop();
+        self.active += 1;
+        if let Some(entity) = entity {
+            self.is_parked.remove(&entity);
+            self.reused += 1;
+        } else {
+            self.spawned += 1;
+        }
+        entity
+    }
+
+    pub fn park(&mut self, commands: &mut Commands, entity: Entity) {
+        if self.is_parked.contains(&entity) {
+            return;
+        }
+        self.active = self.active.saturating_sub(1);
+        if self.parked.len() < MAX_PARKED {
+            commands.entity(entity).insert(Visibility::Hidden);
+            self.parked.push(entity);
+            self.is_parked.insert(entity);
+        } else {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+pub fn reset_bullet_pool(mut commands: Commands) {
+    commands.insert_resource(BulletPool::default());
+}
+
+pub fn print_bullet_pool(pool: Res<BulletPool>) {
+    screen_print!(
+        "bullets: {} active, {} parked, {} spawned, {} reused",
+        pool.active,
+        pool.parked.len(),
+        pool.spawned,
+        pool.reused
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::CommandQueue;
+
+    use super::*;
+
+    #[test]
+    fn parking_twice_only_parks_once() {
+        let mut world = World::new();
+        let bullet = world.spawn_empty().id();
+        let mut queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut queue, &world);
+        let mut pool = BulletPool::default();
+
+        assert_eq!(pool.take(), None);
+        pool.park(&mut commands, bullet);
+        pool.park(&mut commands, bullet);
+        assert_eq!(pool.active, 0);
+        assert_eq!(pool.take(), Some(bullet));
+        assert_eq!(pool.take(), None);
+        assert_eq!(pool.active, 2);
+    }
+}
@@ -0,0 +1,297 @@
+//! The camera following the player, leading ahead of where they move and aim, with zoom and a
+//! trauma based shake anything can push into.
+
+use bevy::input::mouse::{MouseScrollUnit, MouseWheel};
+use bevy_egui::{egui, EguiContexts};
+
+use super::enemies::EnemyDied;
+use super::feedback::DamageDealt;
+use super::{boss, MovingDirection, Player};
+use crate::prelude::*;
+use crate::{world_running, MainState, PlayingState};
+
+const CAMERA_DISTANCE: f32 = 200.0;
+const CAMERA_MAX_SPEED: f32 = 500.0;
+const CAMERA_ACCELERATION: f32 = 2000.0;
+/// How far the camera leans toward the cursor at the edge of the window
+const LOOK_AHEAD: f32 = 150.0;
+const MIN_ZOOM: f32 = 0.6;
+const MAX_ZOOM: f32 = 1.6;
+/// Zoom change per scroll wheel line
+const ZOOM_STEP: f32 = 0.1;
+/// How quickly the zoom catches up with the target, per second
+const ZOOM_SPEED: f32 = 8.0;
+/// Trauma lost per second
+const TRAUMA_DECAY: f32 = 1.5;
+const MAX_SHAKE_OFFSET: f32 = 24.0;
+/// In radians
+const MAX_SHAKE_ANGLE: f32 = 0.05;
+
+pub struct CameraPlugin;
+
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Trauma>()
+            .init_resource::<CameraSettings>()
+            .add_systems(OnEnter(MainState::Playing), spawn_camera)
+            .add_systems(Update, traumatic_events.run_if(world_running))
+            .add_systems(
+                Update,
+                (
+                    scroll_zoom,
+                    apply_zoom,
+                    set_camera_speed,
+                    move_camera,
+                    shake_camera,
+                )
+                    .chain()
+                    .after(boss::keep_in_arena)
+                    .run_if(in_state(PlayingState::ShootyTime)),
+            )
+            .add_systems(Update, camera_ui.run_if(in_state(PlayingState::Editor)));
+    }
+}
+
+/// How shaken up the camera is, from 0 to 1, the shake grows with the square of it
+#[derive(Resource, Default, Debug)]
+pub struct Trauma(f32);
+
+impl Trauma {
+    pub fn add(&mut self, amount: f32) {
+        self.0 = (self.0 + amount).clamp(0.0, 1.0);
+    }
+}
+
+#[derive(Resource, Debug)]
+struct CameraSettings {
+    /// The zoom the camera is easing toward, bigger sees more
+    zoom: f32,
+    /// Multiplier on the shake, zero turns it off
+    shake: f32,
+    look_ahead: bool,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            zoom: 1.0,
+            shake: 1.0,
+            look_ahead: true,
+        }
+    }
+}
+
+#[derive(Component)]
+struct CameraSpeed(Vec2);
+
+#[derive(Component)]
+struct CameraRelative(Vec2);
+
+fn spawn_camera(mut commands: Commands, mut trauma: ResMut<Trauma>) {
+    trauma.0 = 0.0;
+    commands
+        .spawn(Camera2dBundle::default())
+        .insert(CameraSpeed(Vec2::ZERO))
+        .insert(CameraRelative(Vec2::ZERO));
+}
+
+fn traumatic_events(
+    mut damage: EventReader<DamageDealt>,
+    mut died: EventReader<EnemyDied>,
+    players: Query<(), With<Player>>,
+    mut trauma: ResMut<Trauma>,
+) {
+    for event in damage.read() {
+        if players.contains(event.target) {
+            trauma.add(0.35);
+        } else if event.crit {
+            trauma.add(0.15);
+        }
+    }
+    for _ in died.read() {
+        trauma.add(0.2);
+    }
+}
+
+fn scroll_zoom(mut wheel: EventReader<MouseWheel>, mut settings: ResMut<CameraSettings>) {
+    let lines: f32 = wheel
+        .read()
+        .map(|event| match event.unit {
+            MouseScrollUnit::Line => event.y,
+            MouseScrollUnit::Pixel => event.y / 100.0,
+        })
+        .sum();
+    if lines != 0.0 {
+        settings.zoom = (settings.zoom * (1.0 - lines * ZOOM_STEP)).clamp(MIN_ZOOM, MAX_ZOOM);
+    }
+}
+
+fn apply_zoom(
+    mut camera: Query<&mut OrthographicProjection, With<CameraSpeed>>,
+    settings: Res<CameraSettings>,
+    time: Res<Time<Real>>,
+) {
+    let Ok(mut projection) = camera.get_single_mut() else {
+        return;
+    };
+    let difference = settings.zoom - projection.scale;
+    if difference.abs() < f32::EPSILON {
+        return;
+    }
+    projection.scale = if difference.abs() < 0.001 {
+        settings.zoom
+    } else {
+        projection.scale + difference * (ZOOM_SPEED * time.delta_seconds()).min(1.0)
+    };
+}
+
+/// Where the cursor is in the window, from -1 to 1 on both axes with y going up
+fn cursor_lean(window: &Window) -> Vec2 {
+    let Some(cursor) = window.cursor_position() else {
+        return Vec2::ZERO;
+    };
+    let half = Vec2::new(window.width(), window.height()) / 2.0;
+    let lean = (cursor - half) / half;
+    Vec2::new(lean.x, -lean.y).clamp(Vec2::NEG_ONE, Vec2::ONE)
+}
+
+fn move_camera(
+    mut camera: Query<(&mut Transform, &mut CameraRelative, &CameraSpeed), Without<Player>>,
+    player: Query<&Transform, With<Player>>,
+    time: Res<Time>,
+) {
+    let Ok(player_trans) = player.get_single() else {
+        return;
+    };
+
+    for (mut trans, mut relative, speed) in &mut camera {
+        relative.0 += speed.0 * time.delta_seconds();
+        trans.translation =
+            (player_trans.translation.truncate() + relative.0).extend(trans.translation.z);
+    }
+}
+
+fn set_camera_speed(
+    mut camera: Query<(&CameraRelative, &mut CameraSpeed, &OrthographicProjection)>,
+    player: Query<&MovingDirection, With<Player>>,
+    window: Query<&Window>,
+    settings: Res<CameraSettings>,
+    time: Res<Time>,
+) {
+    let Ok(player_moving) = player.get_single() else {
+        return;
+    };
+    let Ok((camera_trans, mut camera_speed, projection)) = camera.get_single_mut() else {
+        return;
+    };
+
+    // Reading the cursor in window space, in world space it would move with the camera
+    let lean = match window.get_single() {
+        Ok(window) if settings.look_ahead => cursor_lean(window),
+        _ => Vec2::ZERO,
+    };
+    let target = player_moving.0 * CAMERA_DISTANCE + lean * LOOK_AHEAD * projection.scale;
+    let delta = target - camera_trans.0;
+
+    if delta.length() < 50.0 {
+        let ideal_speed = delta.length() / 2.0;
+        let factor = camera_speed.0.length() / ideal_speed;
+
+        if factor.is_normal() {
+            camera_speed.0 /= 1. + factor * time.delta_seconds();
+            return;
+        }
+    }
+
+    let delta = delta.normalize_or_zero();
+
+    camera_speed.0 =
+        Vec2::from_angle(delta.to_angle()).normalize_or_zero() * camera_speed.0.length();
+    camera_speed.0 += delta * CAMERA_ACCELERATION * time.delta_seconds();
+    camera_speed.0 = camera_speed.0.clamp_length_max(CAMERA_MAX_SPEED);
+}
+
+/// Offset and angle of the shake at a point in time, smooth waves rather than random jumps
+fn shake(trauma: f32, seconds: f32) -> (Vec2, f32) {
+    let amount = trauma * trauma;
+    let wave = |speed: f32, phase: f32| {
+        (seconds * speed + phase).sin() * 0.6 + (seconds * speed * 2.3 + phase).sin() * 0.4
+    };
+    let offset = Vec2::new(wave(31.0, 0.0), wave(27.0, 1.7)) * MAX_SHAKE_OFFSET * amount;
+    (offset, wave(23.0, 3.1) * MAX_SHAKE_ANGLE * amount)
+}
+
+fn shake_camera(
+    mut camera: Query<&mut Transform, With<CameraSpeed>>,
+    mut trauma: ResMut<Trauma>,
+    settings: Res<CameraSettings>,
+    time: Res<Time<Real>>,
+) {
+    let Ok(mut trans) = camera.get_single_mut() else {
+        return;
+    };
+    // Real time so the shake keeps going through hit-stop
+    let (offset, angle) = shake(trauma.0 * settings.shake, time.elapsed_seconds());
+    trans.translation += offset.extend(0.0);
+    trans.rotation = Quat::from_rotation_z(angle);
+    if trauma.0 > 0.0 {
+        trauma.0 = (trauma.0 - TRAUMA_DECAY * time.delta_seconds()).max(0.0);
+    }
+}
+
+fn camera_ui(mut ctx: EguiContexts, mut settings: ResMut<CameraSettings>) {
+    egui::Window::new("Camera")
+        .anchor(egui::Align2::RIGHT_TOP, egui::Vec2::new(-8.0, 88.0))
+        .default_open(false)
+        .resizable(false)
+        .show(ctx.ctx_mut(), |ui| {
+            let mut edited = CameraSettings {
+                zoom: settings.zoom,
+                shake: settings.shake,
+                look_ahead: settings.look_ahead,
+            };
+            let mut touched = ui
+                .add(egui::Slider::new(&mut edited.zoom, MIN_ZOOM..=MAX_ZOOM).text("Zoom"))
+                .changed();
+            touched |= ui
+                .add(egui::Slider::new(&mut edited.shake, 0.0..=2.0).text("Shake"))
+                .changed();
+            touched |= ui
+                .checkbox(&mut edited.look_ahead, "Look toward the cursor")
+                .changed();
+            if touched {
+                *settings = edited;
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shake_grows_with_trauma() {
+        let (offset, angle) = shake(0.0, 1.3);
+        assert_eq!((offset, angle), (Vec2::ZERO, 0.0));
+
+        for step in 0..100 {
+            let (offset, angle) = shake(1.0, step as f32 * 0.05);
+            assert!(offset.abs().max_element() <= MAX_SHAKE_OFFSET);
+            assert!(angle.abs() <= MAX_SHAKE_ANGLE);
+        }
+        // Half the trauma is a quarter of the shake
+        let (full, _) = shake(1.0, 0.4);
+        let (half, _) = shake(0.5, 0.4);
+        assert!((full / 4.0 - half).length() < 0.001);
+    }
+
+    #[test]
+    fn trauma_stays_in_range() {
+        let mut trauma = Trauma::default();
+        trauma.add(0.7);
+        trauma.add(0.7);
+        assert!((trauma.0 - 1.0).abs() < f32::EPSILON);
+        trauma.add(-5.0);
+        assert!(trauma.0.abs() < f32::EPSILON);
+    }
+}
@@ -0,0 +1,587 @@
+//! Enemies and the behaviours driving them.
+//!
+//! Every enemy kind picks one behaviour from a small library in its [`EnemyStats`].
+
+use std::ops::Range;
+
+use bevy_prng::WyRand;
+use rand::Rng;
+
+use super::animation::{AnimationController, AnimationCues, ENEMY_ANIMATIONS};
+use super::boss::ArenaLock;
+use super::feedback::DamageDealt;
+use super::{Health, HitEvents, Player};
+use crate::collision::{Collider, Collision, Layers};
+use crate::node_editor::{
+    EnemyPatterns,
+    EventBudgetUsage,
+    GraphOwner,
+    NodeEventData,
+    NodeGraph,
+    NodeOutputTrigger,
+    WorldEvent,
+};
+use crate::prelude::*;
+use crate::sound::{PlaySound, Sound};
+use crate::{world_running, MainState, ZIndex};
+
+const MAX_ENEMIES: usize = 40;
+const SPAWN_DISTANCE: Range<f32> = 700.0..900.0;
+const SPAWN_INTERVAL: f32 = 2.0;
+/// Damage done by a single deal damage node
+const NODE_DAMAGE: f32 = 10.0;
+/// Chance for the players damage nodes to crit
+const CRIT_CHANCE: f64 = 0.1;
+const CRIT_MULTIPLIER: f32 = 2.0;
+
+pub struct EnemyPlugin;
+
+impl Plugin for EnemyPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EnemySpawner>()
+            .add_event::<EnemyFire>()
+            .add_event::<EnemyDied>()
+            .add_systems(Startup, register_patterns)
+            .add_systems(OnEnter(MainState::Playing), reset_spawner)
+            .add_systems(
+                Update,
+                (
+                    // No more small fry while a boss fight is going on
+                    spawn_enemies.run_if(not(resource_exists::<ArenaLock>)),
+                    run_behaviours,
+                    deal_damage,
+                    contact_damage,
+                )
+                    .run_if(world_running),
+            );
+    }
+}
+
+/// An enemy shooting, turned into a bullet by `spawn_bullet`
+#[derive(Event)]
+pub struct EnemyFire {
+    pub loc: Vec2,
+    pub dir: Vec2,
+}
+
+#[derive(Event)]
+pub struct EnemyDied {
+    pub loc: Vec2,
+    pub xp: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Behaviour {
+    /// Walks straight at the player
+    Chase,
+    /// Hangs around `range` away from the player, shooting every `cooldown` seconds
+    Ranged { range: f32, cooldown: f32 },
+    /// Stops when in `range`, winds up for `windup` seconds and dashes at the player
+    Charge {
+        range: f32,
+        windup: f32,
+        dash_speed: f32,
+        dash_time: f32,
+    },
+    /// Chases the player while keeping `spacing` away from the rest of the swarm
+    Swarm { spacing: f32 },
+}
+
+struct EnemyStats {
+    name: &'static str,
+    health: f32,
+    speed: f32,
+    radius: f32,
+    /// Damage per second while touching the player
+    contact_damage: f32,
+    color: Color,
+    behaviour: Behaviour,
+    /// Experience dropped on death
+    xp: u32,
+    /// How many spawn together
+    group: usize,
+    /// Default node graph fired instead of plain bullets, in the editors text format, can be
+    /// changed in the editor through [`EnemyPatterns`]
+    pattern: Option<&'static str>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum EnemyKind {
+    Grunt,
+    Gunner,
+    Charger,
+    Swarmer,
+    Caster,
+    /// The boss, never spawned randomly, see [`super::boss`]
+    Warden,
+}
+
+impl EnemyKind {
+    /// Every kind that can spawn on its own
+    const ALL: [Self; 5] = [
+        Self::Grunt,
+        Self::Gunner,
+        Self::Charger,
+        Self::Swarmer,
+        Self::Caster,
+    ];
+
+    const fn stats(self) -> EnemyStats {
+        match self {
+            Self::Grunt => EnemyStats {
+                name: "Grunt",
+                health: 30.0,
+                speed: 120.0,
+                radius: 20.0,
+                contact_damage: 20.0,
+                color: Color::rgb(0.6, 0.8, 0.3),
+                behaviour: Behaviour::Chase,
+                xp: 3,
+                group: 1,
+                pattern: None,
+            },
+            Self::Gunner => EnemyStats {
+                name: "Gunner",
+                health: 20.0,
+                speed: 100.0,
+                radius: 18.0,
+                contact_damage: 10.0,
+                color: Color::rgb(0.9, 0.5, 0.2),
+                behaviour: Behaviour::Ranged {
+                    range: 350.0,
+                    cooldown: 1.5,
+                },
+                xp: 3,
+                group: 1,
+                pattern: None,
+            },
+            Self::Charger => EnemyStats {
+                name: "Charger",
+                health: 50.0,
+                speed: 90.0,
+                radius: 26.0,
+                contact_damage: 40.0,
+                color: Color::rgb(0.7, 0.2, 0.7),
+                behaviour: Behaviour::Charge {
+                    range: 300.0,
+                    windup: 0.8,
+                    dash_speed: 700.0,
+                    dash_time: 0.6,
+                },
+                xp: 5,
+                group: 1,
+                pattern: None,
+            },
+            Self::Swarmer => EnemyStats {
+                name: "Swarmer",
+                health: 10.0,
+                speed: 160.0,
+                radius: 12.0,
+                contact_damage: 8.0,
+                color: Color::rgb(0.3, 0.6, 0.9),
+                behaviour: Behaviour::Swarm { spacing: 40.0 },
+                xp: 1,
+                group: 6,
+                pattern: None,
+            },
+            Self::Caster => EnemyStats {
+                name: "Caster",
+                health: 40.0,
+                speed: 80.0,
+                radius: 22.0,
+                contact_damage: 10.0,
+                color: Color::rgb(0.9, 0.2, 0.3),
+                behaviour: Behaviour::Ranged {
+                    range: 400.0,
+                    cooldown: 2.5,
+                },
+                xp: 5,
+                group: 1,
+                pattern: Some("on_shoot -> spread(5, 60deg) -> spawn_bullet.hit -> deal_dmg"),
+            },
+            // Its patterns come from its boss phases
+            Self::Warden => EnemyStats {
+                name: "Warden",
+                health: 600.0,
+                speed: 60.0,
+                radius: 64.0,
+                contact_damage: 30.0,
+                color: Color::rgb(0.5, 0.1, 0.1),
+                behaviour: Behaviour::Ranged {
+                    range: 300.0,
+                    cooldown: 1.2,
+                },
+                xp: 50,
+                group: 1,
+                pattern: None,
+            },
+        }
+    }
+}
+
+#[derive(Component)]
+struct Enemy(EnemyKind);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ChargePhase {
+    Approach,
+    Telegraph,
+    Dash,
+    Recover,
+}
+
+/// Per enemy state the behaviours keep between frames
+#[derive(Component)]
+struct Brain {
+    timer: Timer,
+    phase: ChargePhase,
+    dir: Vec2,
+}
+
+impl Brain {
+    fn new(behaviour: Behaviour) -> Self {
+        let timer = match behaviour {
+            Behaviour::Ranged { cooldown, .. } => {
+                Timer::from_seconds(cooldown, TimerMode::Repeating)
+            }
+            Behaviour::Chase | Behaviour::Charge { .. } | Behaviour::Swarm { .. } => {
+                Timer::default()
+            }
+        };
+        Self {
+            timer,
+            phase: ChargePhase::Approach,
+            dir: Vec2::ZERO,
+        }
+    }
+}
+
+#[derive(Resource)]
+struct EnemySpawner(Timer);
+
+impl Default for EnemySpawner {
+    fn default() -> Self {
+        Self(Timer::from_seconds(SPAWN_INTERVAL, TimerMode::Repeating))
+    }
+}
+
+fn reset_spawner(mut commands: Commands) {
+    commands.insert_resource(EnemySpawner::default());
+}
+
+fn register_patterns(mut patterns: ResMut<EnemyPatterns>) {
+    for kind in EnemyKind::ALL {
+        let stats = kind.stats();
+        if let Some(pattern) = stats.pattern {
+            patterns.register(stats.name, pattern);
+        }
+    }
+}
+
+fn spawn_enemies(
+    mut commands: Commands,
+    mut spawner: ResMut<EnemySpawner>,
+    patterns: Res<EnemyPatterns>,
+    enemies: Query<(), With<Enemy>>,
+    player: Query<&Transform, With<Player>>,
+    mut rng: ResMut<GlobalEntropy<WyRand>>,
+    time: Res<Time>,
+) {
+    if !spawner.0.tick(time.delta()).just_finished() || enemies.iter().len() >= MAX_ENEMIES {
+        return;
+    }
+    let Ok(player) = player.get_single() else {
+        return;
+    };
+
+    let kind = EnemyKind::ALL[rng.gen_range(0..EnemyKind::ALL.len())];
+    let center = player.translation.truncate()
+        + Vec2::from_angle(rng.gen_range(0.0..std::f32::consts::TAU))
+            * rng.gen_range(SPAWN_DISTANCE);
+    for _ in 0..kind.stats().group {
+        let offset = Vec2::new(rng.gen_range(-40.0..40.0), rng.gen_range(-40.0..40.0));
+        spawn_enemy(&mut commands, &patterns, kind, center + offset);
+    }
+}
+
+pub(super) fn spawn_enemy(
+    commands: &mut Commands,
+    patterns: &EnemyPatterns,
+    kind: EnemyKind,
+    loc: Vec2,
+) -> Entity {
+    let stats = kind.stats();
+    let mut enemy = commands.spawn((
+        Gc(MainState::Playing),
+        Enemy(kind),
+        Brain::new(stats.behaviour),
+        Health::new(stats.health),
+        ShapeBundle {
+            path: GeometryBuilder::build_as(&shapes::Circle {
+                radius: stats.radius,
+                center: Vec2::ZERO,
+            }),
+            spatial: SpatialBundle::from_transform(Transform::from_translation(
+                loc.extend(ZIndex::Enemy.into()),
+            )),
+            ..default()
+        },
+        Fill::color(stats.color),
+        Stroke::new(Color::BLACK, 2.0),
+        Collider::circle(
+            stats.radius,
+            Layers::ENEMY,
+            Layers::PLAYER | Layers::PLAYER_BULLET | Layers::WALL,
+        ),
+        AnimationController::new(&ENEMY_ANIMATIONS, None),
+        AnimationCues::default(),
+        Name::new(stats.name),
+    ));
+
+    if let Some(graph) = patterns.graph(stats.name) {
+        enemy.insert(graph);
+    }
+    enemy.id()
+}
+
+fn run_behaviours(
+    mut enemies: Query<(Entity, &Enemy, &mut Brain, &mut Transform, &mut Fill), Without<Player>>,
+    player: Query<&Transform, With<Player>>,
+    graphs: Query<&NodeGraph>,
+    mut fire: EventWriter<EnemyFire>,
+    mut triggers: EventWriter<NodeOutputTrigger>,
+    mut usage: ResMut<EventBudgetUsage>,
+    time: Res<Time>,
+) {
+    let Ok(player) = player.get_single() else {
+        return;
+    };
+    let player = player.translation.truncate();
+    let swarm: Vec<Vec2> = enemies
+        .iter()
+        .filter(|(_, enemy, ..)| matches!(enemy.0.stats().behaviour, Behaviour::Swarm { .. }))
+        .map(|(_, _, _, trans, _)| trans.translation.truncate())
+        .collect();
+    let mut shots = Vec::new();
+
+    for (entity, enemy, mut brain, mut trans, mut fill) in &mut enemies {
+        let stats = enemy.0.stats();
+        let loc = trans.translation.truncate();
+        let to_player = player - loc;
+
+        let velocity = match stats.behaviour {
+            Behaviour::Chase => to_player.normalize_or_zero() * stats.speed,
+            Behaviour::Ranged { range, .. } => {
+                let dir = to_player.normalize_or_zero();
+                if brain.timer.tick(time.delta()).just_finished()
+                    && to_player.length() < range * 1.5
+                {
+                    shots.push((entity, loc, dir));
+                }
+                ranged(to_player, range) * stats.speed
+            }
+            Behaviour::Charge { .. } => charge(&mut brain, &stats, to_player, &time, &mut fill),
+            Behaviour::Swarm { spacing } => {
+                swarm_dir(loc, to_player, &swarm, spacing) * stats.speed
+            }
+        };
+        trans.translation += (velocity * time.delta_seconds()).extend(0.0);
+    }
+
+    for (entity, loc, dir) in shots {
+        let Ok(graph) = graphs.get(entity) else {
+            fire.send(EnemyFire { loc, dir });
+            continue;
+        };
+        triggers.send(NodeOutputTrigger {
+            data: NodeEventData {
+                loc: Some(loc),
+                dir: Some(dir),
+                shot: usage.new_shot(),
+                ..default()
+            },
+            owner: GraphOwner::Entity(entity),
+            node: graph.trigger,
+            output_index: 0,
+        });
+    }
+}
+
+/// Closes in when too far, backs off when too close and strafes in between
+fn ranged(to_player: Vec2, range: f32) -> Vec2 {
+    let dir = to_player.normalize_or_zero();
+    let distance = to_player.length();
+    if distance > range * 1.2 {
+        dir
+    } else if distance < range * 0.8 {
+        -dir
+    } else {
+        dir.perp() * 0.5
+    }
+}
+
+fn charge(
+    brain: &mut Brain,
+    stats: &EnemyStats,
+    to_player: Vec2,
+    time: &Time,
+    fill: &mut Fill,
+) -> Vec2 {
+    let Behaviour::Charge {
+        range,
+        windup,
+        dash_speed,
+        dash_time,
+    } = stats.behaviour
+    else {
+        return Vec2::ZERO;
+    };
+    let finished = brain.timer.tick(time.delta()).finished();
+
+    match brain.phase {
+        ChargePhase::Approach => {
+            if to_player.length() > range {
+                return to_player.normalize_or_zero() * stats.speed;
+            }
+            brain.phase = ChargePhase::Telegraph;
+            brain.timer = Timer::from_seconds(windup, TimerMode::Once);
+            fill.color = Color::WHITE;
+        }
+        ChargePhase::Telegraph => {
+            // Keep aiming until the very last moment of the windup
+            brain.dir = to_player.normalize_or_zero();
+            if finished {
+                brain.phase = ChargePhase::Dash;
+                brain.timer = Timer::from_seconds(dash_time, TimerMode::Once);
+                fill.color = stats.color;
+            }
+        }
+        ChargePhase::Dash => {
+            if !finished {
+                return brain.dir * dash_speed;
+            }
+            brain.phase = ChargePhase::Recover;
+            brain.timer = Timer::from_seconds(windup, TimerMode::Once);
+        }
+        ChargePhase::Recover => {
+            if finished {
+                brain.phase = ChargePhase::Approach;
+            }
+        }
+    }
+    Vec2::ZERO
+}
+
+fn swarm_dir(loc: Vec2, to_player: Vec2, swarm: &[Vec2], spacing: f32) -> Vec2 {
+    let separation: Vec2 = swarm
+        .iter()
+        .map(|other| loc - *other)
+        .filter(|offset| *offset != Vec2::ZERO && offset.length() < spacing)
+        .map(|offset| offset.normalize() * (spacing - offset.length()) / spacing)
+        .sum();
+    (to_player.normalize_or_zero() + separation * 1.5).normalize_or_zero()
+}
+
+fn deal_damage(
+    mut commands: Commands,
+    mut events: EventReader<WorldEvent>,
+    mut targets: Query<(&mut Health, &Transform, Option<&Enemy>)>,
+    mut hits: HitEvents,
+    mut died: EventWriter<EnemyDied>,
+    mut rng: ResMut<GlobalEntropy<WyRand>>,
+) {
+    for event in events.read() {
+        let WorldEvent::DealDmg { data, owner, id } = event else {
+            continue;
+        };
+        let Some(target) = data.target else {
+            continue;
+        };
+        let Ok((mut health, trans, enemy)) = targets.get_mut(target) else {
+            continue;
+        };
+        if health.current <= 0.0 {
+            continue;
+        }
+        let crit = *owner == GraphOwner::Player && rng.gen_bool(CRIT_CHANCE);
+        let amount = if crit {
+            NODE_DAMAGE * CRIT_MULTIPLIER
+        } else {
+            NODE_DAMAGE
+        };
+        hits.damage.send(DamageDealt {
+            target,
+            loc: data.loc.unwrap_or_else(|| trans.translation.truncate()),
+            amount,
+            crit,
+        });
+        if !health.damage(amount) {
+            continue;
+        }
+
+        hits.triggers.send(NodeOutputTrigger {
+            data: NodeEventData {
+                loc: Some(trans.translation.truncate()),
+                target: None,
+                ..data.clone()
+            },
+            owner: *owner,
+            node: *id,
+            output_index: 0,
+        });
+        if let Some(enemy) = enemy {
+            died.send(EnemyDied {
+                loc: trans.translation.truncate(),
+                xp: enemy.0.stats().xp,
+            });
+            hits.sounds.send(PlaySound(Sound::Kill));
+            commands.entity(target).despawn_recursive();
+        }
+    }
+}
+
+fn contact_damage(
+    mut collisions: EventReader<Collision>,
+    enemies: Query<&Enemy>,
+    mut player: Query<&mut Health, With<Player>>,
+    time: Res<Time>,
+) {
+    for collision in collisions.read() {
+        let Some((player_id, enemy_id)) = collision.between(Layers::PLAYER, Layers::ENEMY) else {
+            continue;
+        };
+        let (Ok(enemy), Ok(mut health)) = (enemies.get(enemy_id), player.get_mut(player_id)) else {
+            continue;
+        };
+        health.damage(enemy.0.stats().contact_damage * time.delta_seconds());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranged_keeps_its_distance() {
+        assert_eq!(ranged(Vec2::new(1000.0, 0.0), 350.0), Vec2::X);
+        assert_eq!(ranged(Vec2::new(100.0, 0.0), 350.0), -Vec2::X);
+        assert_eq!(ranged(Vec2::new(350.0, 0.0), 350.0), Vec2::new(0.0, 0.5));
+    }
+
+    #[test]
+    fn swarm_pushes_apart_when_crowded() {
+        let swarm = [Vec2::ZERO, Vec2::new(0.0, 10.0)];
+        let dir = swarm_dir(Vec2::ZERO, Vec2::new(100.0, 0.0), &swarm, 40.0);
+        assert!(dir.x > 0.0 && dir.y < 0.0, "{dir:?}");
+        let alone = swarm_dir(Vec2::ZERO, Vec2::new(100.0, 0.0), &[Vec2::ZERO], 40.0);
+        assert_eq!(alone, Vec2::X);
+    }
+
+    #[test]
+    fn only_the_killing_blow_reports_death() {
+        let mut health = Health::new(15.0);
+        assert!(!health.damage(10.0));
+        assert!(health.damage(10.0));
+        assert!(!health.damage(10.0));
+    }
+}
@@ -0,0 +1,205 @@
+//! Making hits feel like hits, floating damage numbers and a short freeze on crits.
+
+use bevy_egui::{egui, EguiContexts};
+
+use super::Player;
+use crate::prelude::*;
+use crate::{world_running, MainState, PlayingState, ZIndex};
+
+/// Seconds a damage number floats for
+const NUMBER_LIFETIME: f32 = 0.7;
+const NUMBER_RISE: f32 = 60.0;
+const NUMBER_COLOR: Color = Color::WHITE;
+const CRIT_COLOR: Color = Color::rgb(1.0, 0.75, 0.1);
+const PLAYER_HIT_COLOR: Color = Color::rgb(1.0, 0.3, 0.3);
+
+pub struct FeedbackPlugin;
+
+impl Plugin for FeedbackPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<DamageDealt>()
+            .init_resource::<FeedbackSettings>()
+            .init_resource::<HitStop>()
+            .add_systems(
+                Update,
+                (spawn_damage_numbers, float_damage_numbers, start_hit_stop).run_if(world_running),
+            )
+            // Hit-stop counts real time, the virtual clock is the thing it stops. It can start
+            // anywhere the world runs, the live editor too, so it has to be able to end there
+            .add_systems(Update, end_hit_stop.run_if(in_state(MainState::Playing)))
+            .add_systems(Update, feedback_ui.run_if(in_state(PlayingState::Editor)))
+            .add_systems(OnEnter(MainState::Playing), cancel_hit_stop);
+    }
+}
+
+/// Sent whenever damage lands on something that was still alive
+#[derive(Event, Debug, Clone, Copy)]
+pub struct DamageDealt {
+    pub target: Entity,
+    pub loc: Vec2,
+    pub amount: f32,
+    pub crit: bool,
+}
+
+#[derive(Resource, Debug)]
+pub(super) struct FeedbackSettings {
+    pub(super) damage_numbers: bool,
+    pub(super) flash: bool,
+    /// Seconds the world freezes for on a crit, zero turns it off
+    pub(super) hit_stop: f32,
+}
+
+impl Default for FeedbackSettings {
+    fn default() -> Self {
+        Self {
+            damage_numbers: true,
+            flash: true,
+            hit_stop: 0.05,
+        }
+    }
+}
+
+#[derive(Resource, Default, Debug)]
+struct HitStop {
+    /// Real time left, `None` when the world isn't stopped
+    left: Option<f32>,
+}
+
+#[derive(Component)]
+struct DamageNumber {
+    age: f32,
+    color: Color,
+}
+
+fn damage_label(amount: f32, crit: bool) -> String {
+    if crit {
+        format!("{amount:.0}!")
+    } else {
+        format!("{amount:.0}")
+    }
+}
+
+fn spawn_damage_numbers(
+    mut commands: Commands,
+    mut events: EventReader<DamageDealt>,
+    players: Query<(), With<Player>>,
+    settings: Res<FeedbackSettings>,
+) {
+    for event in events.read() {
+        if !settings.damage_numbers {
+            continue;
+        }
+        let (color, font_size) = if players.contains(event.target) {
+            (PLAYER_HIT_COLOR, 24.0)
+        } else if event.crit {
+            (CRIT_COLOR, 32.0)
+        } else {
+            (NUMBER_COLOR, 24.0)
+        };
+        commands.spawn((
+            Gc(MainState::Playing),
+            DamageNumber { age: 0.0, color },
+            Text2dBundle {
+                text: Text::from_section(
+                    damage_label(event.amount, event.crit),
+                    TextStyle {
+                        font_size,
+                        color,
+                        ..default()
+                    },
+                ),
+                transform: Transform::from_translation(event.loc.extend(ZIndex::Decals.into())),
+                ..default()
+            },
+            Name::new("Damage Number"),
+        ));
+    }
+}
+
+fn float_damage_numbers(
+    mut commands: Commands,
+    mut numbers: Query<(Entity, &mut DamageNumber, &mut Transform, &mut Text)>,
+    time: Res<Time>,
+) {
+    for (entity, mut number, mut trans, mut text) in &mut numbers {
+        number.age += time.delta_seconds();
+        if number.age >= NUMBER_LIFETIME {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        }
+        let progress = number.age / NUMBER_LIFETIME;
+        // Rises fast and slows down, fading out over the second half
+        trans.translation.y += NUMBER_RISE * 2.0 * (1.0 - progress) * time.delta_seconds();
+        let alpha = (2.0 - progress * 2.0).min(1.0);
+        text.sections[0].style.color = number.color.with_a(alpha);
+    }
+}
+
+fn start_hit_stop(
+    mut events: EventReader<DamageDealt>,
+    settings: Res<FeedbackSettings>,
+    mut stop: ResMut<HitStop>,
+    mut time: ResMut<Time<Virtual>>,
+) {
+    let crits = events.read().filter(|event| event.crit).count();
+    if crits == 0 || settings.hit_stop <= 0.0 || stop.left.is_some() {
+        return;
+    }
+    stop.left = Some(settings.hit_stop);
+    time.pause();
+}
+
+fn end_hit_stop(mut stop: ResMut<HitStop>, mut time: ResMut<Time<Virtual>>, real: Res<Time<Real>>) {
+    let Some(left) = stop.left else {
+        return;
+    };
+    let left = left - real.delta_seconds();
+    if left > 0.0 {
+        stop.left = Some(left);
+    } else {
+        stop.left = None;
+        time.unpause();
+    }
+}
+
+fn cancel_hit_stop(mut stop: ResMut<HitStop>, mut time: ResMut<Time<Virtual>>) {
+    if stop.left.take().is_some() {
+        time.unpause();
+    }
+}
+
+fn feedback_ui(mut ctx: EguiContexts, mut settings: ResMut<FeedbackSettings>) {
+    egui::Window::new("Hit Feedback")
+        .anchor(egui::Align2::RIGHT_TOP, egui::Vec2::new(-8.0, 48.0))
+        .default_open(false)
+        .resizable(false)
+        .show(ctx.ctx_mut(), |ui| {
+            let mut changed = FeedbackSettings {
+                damage_numbers: settings.damage_numbers,
+                flash: settings.flash,
+                hit_stop: settings.hit_stop,
+            };
+            let mut touched = ui
+                .checkbox(&mut changed.damage_numbers, "Damage numbers")
+                .changed();
+            touched |= ui.checkbox(&mut changed.flash, "Hit flash").changed();
+            touched |= ui
+                .add(egui::Slider::new(&mut changed.hit_stop, 0.0..=0.2).text("Hit-stop seconds"))
+                .changed();
+            if touched {
+                *settings = changed;
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crits_are_marked() {
+        assert_eq!(damage_label(10.0, false), "10");
+        assert_eq!(damage_label(20.0, true), "20!");
+        assert_eq!(damage_label(7.6, false), "8");
+    }
+}
@@ -0,0 +1,288 @@
+//! The heads up display shown while playing, health, experience and event budget bars plus how the
+//! run is going.
+
+use bevy::diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin};
+use bevy::ecs::system::SystemParam;
+
+use super::boss::{ArenaLock, BossTimer};
+use super::enemies::EnemyDied;
+use super::progression::Experience;
+use super::{Health, LastShot, Player};
+use crate::node_editor::{EventBudget, EventBudgetUsage, GraphName};
+use crate::prelude::*;
+use crate::{world_running, MainState, PlayingState};
+
+/// Score for every point of experience an enemy was worth
+const SCORE_PER_XP: u32 = 10;
+const BAR_WIDTH: f32 = 240.0;
+
+pub struct HudPlugin;
+
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<FrameTimeDiagnosticsPlugin>() {
+            app.add_plugins(FrameTimeDiagnosticsPlugin);
+        }
+        app.init_resource::<RunStats>()
+            .add_systems(OnEnter(MainState::Playing), reset_stats)
+            // Spawned fresh every time so it is gone whenever the editor or a level up is open
+            .add_systems(OnEnter(PlayingState::ShootyTime), spawn_hud)
+            .add_systems(Update, count_kills.run_if(world_running))
+            .add_systems(Update, count_waves.run_if(resource_added::<ArenaLock>))
+            .add_systems(
+                Update,
+                (update_bars, update_texts).run_if(in_state(PlayingState::ShootyTime)),
+            );
+    }
+}
+
+#[derive(Resource, Default, Debug)]
+struct RunStats {
+    kills: u32,
+    score: u32,
+    /// Boss fights started, every boss closes a wave
+    bosses: u32,
+}
+
+#[derive(Component, Clone, Copy)]
+enum HudBar {
+    Health,
+    Experience,
+    /// How much of the per shot event budget the last shot has used
+    Budget,
+}
+
+#[derive(Component, Clone, Copy)]
+enum HudText {
+    Health,
+    Level,
+    Budget,
+    Graph,
+    Kills,
+    Score,
+    Wave,
+    Fps,
+}
+
+fn reset_stats(mut commands: Commands) {
+    commands.insert_resource(RunStats::default());
+}
+
+fn count_kills(mut died: EventReader<EnemyDied>, mut stats: ResMut<RunStats>) {
+    for event in died.read() {
+        stats.kills += 1;
+        stats.score += event.xp * SCORE_PER_XP;
+    }
+}
+
+fn count_waves(mut stats: ResMut<RunStats>) {
+    stats.bosses += 1;
+}
+
+fn text(kind: HudText, font_size: f32) -> (HudText, TextBundle) {
+    (
+        kind,
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size,
+                color: Color::WHITE,
+                ..default()
+            },
+        ),
+    )
+}
+
+fn bar(parent: &mut ChildBuilder, kind: HudBar, color: Color) {
+    parent
+        .spawn(NodeBundle {
+            style: Style {
+                width: Val::Px(BAR_WIDTH),
+                height: Val::Px(12.0),
+                ..default()
+            },
+            background_color: Color::rgba(0.1, 0.1, 0.1, 0.8).into(),
+            ..default()
+        })
+        .with_children(|background| {
+            background.spawn((
+                kind,
+                NodeBundle {
+                    style: Style {
+                        width: Val::Percent(100.0),
+                        height: Val::Percent(100.0),
+                        ..default()
+                    },
+                    background_color: color.into(),
+                    ..default()
+                },
+            ));
+        });
+}
+
+fn corner(left: bool) -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(16.0),
+            left: if left { Val::Px(16.0) } else { Val::Auto },
+            right: if left { Val::Auto } else { Val::Px(16.0) },
+            flex_direction: FlexDirection::Column,
+            align_items: if left {
+                AlignItems::Start
+            } else {
+                AlignItems::End
+            },
+            row_gap: Val::Px(4.0),
+            ..default()
+        },
+        ..default()
+    }
+}
+
+fn spawn_hud(mut commands: Commands) {
+    commands
+        .spawn((
+            Gc(PlayingState::ShootyTime),
+            corner(true),
+            Name::new("Hud Status"),
+        ))
+        .with_children(|status| {
+            status.spawn(text(HudText::Health, 18.0));
+            bar(status, HudBar::Health, Color::rgb(0.85, 0.2, 0.2));
+            status.spawn(text(HudText::Level, 18.0));
+            bar(status, HudBar::Experience, Color::LIME_GREEN);
+            status.spawn(text(HudText::Budget, 18.0));
+            bar(status, HudBar::Budget, Color::rgb(0.3, 0.55, 1.0));
+            status.spawn(text(HudText::Graph, 18.0));
+        });
+    commands
+        .spawn((
+            Gc(PlayingState::ShootyTime),
+            corner(false),
+            Name::new("Hud Run"),
+        ))
+        .with_children(|run| {
+            run.spawn(text(HudText::Score, 24.0));
+            run.spawn(text(HudText::Kills, 18.0));
+            run.spawn(text(HudText::Wave, 18.0));
+            run.spawn(text(HudText::Fps, 14.0));
+        });
+}
+
+/// Everything the HUD shows, gathered from around the game
+#[derive(SystemParam)]
+struct HudSources<'w, 's> {
+    player: Query<'w, 's, &'static Health, With<Player>>,
+    experience: Res<'w, Experience>,
+    stats: Res<'w, RunStats>,
+    name: Res<'w, GraphName>,
+    boss: Res<'w, BossTimer>,
+    arena: Option<Res<'w, ArenaLock>>,
+    budget: Res<'w, EventBudget>,
+    usage: Res<'w, EventBudgetUsage>,
+    last_shot: Res<'w, LastShot>,
+    diagnostics: Res<'w, DiagnosticsStore>,
+}
+
+impl HudSources<'_, '_> {
+    fn budget_spent(&self) -> usize {
+        self.last_shot.0.map_or(0, |shot| self.usage.spent(shot))
+    }
+}
+
+fn update_bars(sources: HudSources, mut bars: Query<(&HudBar, &mut Style)>) {
+    let health = sources
+        .player
+        .get_single()
+        .map_or(0.0, |health| health.current / health.max);
+    let xp = sources.experience.xp as f32 / sources.experience.needed() as f32;
+    let budget = sources.budget_spent() as f32 / sources.budget.per_shot.max(1) as f32;
+    for (bar, mut style) in &mut bars {
+        let fraction = match bar {
+            HudBar::Health => health,
+            HudBar::Experience => xp,
+            HudBar::Budget => budget,
+        };
+        style.width = Val::Percent(fraction.clamp(0.0, 1.0) * 100.0);
+    }
+}
+
+/// Seconds as `m:ss`
+fn clock(seconds: f32) -> String {
+    let seconds = seconds.ceil() as u32;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// Which wave the run is on and what is next, a wave ends with its boss
+fn wave_label(bosses: u32, fighting: bool, boss_in: f32) -> String {
+    if fighting {
+        format!("Wave {bosses}: boss fight!")
+    } else {
+        format!("Wave {}: boss in {}", bosses + 1, clock(boss_in))
+    }
+}
+
+fn update_texts(sources: HudSources, mut texts: Query<(&HudText, &mut Text)>) {
+    let HudSources {
+        player,
+        experience,
+        stats,
+        name,
+        boss,
+        arena,
+        budget,
+        diagnostics,
+        ..
+    } = &sources;
+    for (kind, mut text) in &mut texts {
+        let value = match kind {
+            HudText::Health => player.get_single().map_or_else(
+                |_| String::from("Dead"),
+                |health| format!("{:.0}/{:.0}", health.current, health.max),
+            ),
+            HudText::Level => format!(
+                "Level {} ({}/{} xp)",
+                experience.level,
+                experience.xp,
+                experience.needed()
+            ),
+            HudText::Budget => format!(
+                "Shot budget {}/{}",
+                sources.budget_spent().min(budget.per_shot),
+                budget.per_shot
+            ),
+            HudText::Graph => name.0.clone(),
+            HudText::Kills => format!("{} kills", stats.kills),
+            HudText::Score => format!("{:06}", stats.score),
+            HudText::Wave => wave_label(stats.bosses, arena.is_some(), boss.0.remaining_secs()),
+            HudText::Fps => diagnostics
+                .get(&FrameTimeDiagnosticsPlugin::FPS)
+                .and_then(bevy::diagnostic::Diagnostic::smoothed)
+                .map_or_else(|| String::from("-- fps"), |fps| format!("{fps:.0} fps")),
+        };
+        if text.sections[0].value != value {
+            text.sections[0].value = value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clock_rounds_up_to_whole_seconds() {
+        assert_eq!(clock(90.0), "1:30");
+        assert_eq!(clock(59.2), "1:00");
+        assert_eq!(clock(4.5), "0:05");
+        assert_eq!(clock(0.0), "0:00");
+    }
+
+    #[test]
+    fn waves_end_with_their_boss() {
+        assert_eq!(wave_label(0, false, 75.0), "Wave 1: boss in 1:15");
+        assert_eq!(wave_label(1, true, 0.0), "Wave 1: boss fight!");
+        assert_eq!(wave_label(1, false, 90.0), "Wave 2: boss in 1:30");
+    }
+}
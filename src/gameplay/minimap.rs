@@ -0,0 +1,266 @@
+//! A minimap in the corner showing what is around the player, and arrows at the edge of the
+//! screen pointing at bosses out of view.
+
+use bevy::utils::HashSet;
+
+use super::boss::Boss;
+use super::Player;
+use crate::collision::{Collider, Layers};
+use crate::prelude::*;
+use crate::{MainState, PlayingState, ZIndex};
+
+/// Width and height of the minimap in pixels
+const MAP_SIZE: f32 = 180.0;
+/// World distance from the player to the edge of the minimap
+const MAP_RANGE: f32 = 1500.0;
+/// Most dots drawn at once, anything past this is left off
+const MAX_DOTS: usize = 128;
+const PLAYER_COLOR: Color = Color::WHITE;
+const ENEMY_COLOR: Color = Color::rgb(0.9, 0.25, 0.25);
+const BOSS_COLOR: Color = Color::rgb(0.9, 0.2, 0.9);
+const PICKUP_COLOR: Color = Color::LIME_GREEN;
+/// Pixels between a boss arrow and the edge of the screen
+const ARROW_MARGIN: f32 = 40.0;
+
+pub struct MinimapPlugin;
+
+impl Plugin for MinimapPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(PlayingState::ShootyTime), spawn_minimap)
+            .add_systems(
+                Update,
+                (update_minimap, point_at_bosses).run_if(in_state(PlayingState::ShootyTime)),
+            );
+    }
+}
+
+#[derive(Component)]
+struct MinimapFrame;
+
+/// A reusable dot on the minimap, hidden when there is nothing for it to show
+#[derive(Component)]
+struct MinimapDot;
+
+/// Points at a boss from the edge of the screen while it is out of view
+#[derive(Component)]
+struct BossArrow(Entity);
+
+fn dot(color: Color, size: f32) -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            width: Val::Px(size),
+            height: Val::Px(size),
+            left: Val::Px((MAP_SIZE - size) / 2.0),
+            top: Val::Px((MAP_SIZE - size) / 2.0),
+            ..default()
+        },
+        background_color: color.into(),
+        ..default()
+    }
+}
+
+fn spawn_minimap(mut commands: Commands) {
+    commands
+        .spawn((
+            Gc(PlayingState::ShootyTime),
+            MinimapFrame,
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    bottom: Val::Px(16.0),
+                    right: Val::Px(16.0),
+                    width: Val::Px(MAP_SIZE),
+                    height: Val::Px(MAP_SIZE),
+                    border: UiRect::all(Val::Px(2.0)),
+                    overflow: Overflow::clip(),
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+                border_color: Color::rgba(1.0, 1.0, 1.0, 0.4).into(),
+                ..default()
+            },
+            Name::new("Minimap"),
+        ))
+        .with_children(|map| {
+            map.spawn(dot(PLAYER_COLOR, 6.0));
+        });
+}
+
+/// Where something `offset` away from the player goes on the minimap, `None` when out of range
+fn map_position(offset: Vec2) -> Option<Vec2> {
+    if offset.abs().max_element() > MAP_RANGE {
+        return None;
+    }
+    let scaled = offset / MAP_RANGE * MAP_SIZE / 2.0;
+    // UI goes down from the top
+    Some(Vec2::new(
+        MAP_SIZE / 2.0 + scaled.x,
+        MAP_SIZE / 2.0 - scaled.y,
+    ))
+}
+
+fn update_minimap(
+    mut commands: Commands,
+    player: Query<&GlobalTransform, With<Player>>,
+    things: Query<(&GlobalTransform, &Collider, Has<Boss>)>,
+    frame: Query<Entity, With<MinimapFrame>>,
+    mut dots: Query<(&mut Style, &mut BackgroundColor), With<MinimapDot>>,
+) {
+    let (Ok(player), Ok(frame)) = (player.get_single(), frame.get_single()) else {
+        return;
+    };
+    let center = player.translation().truncate();
+
+    let marks: Vec<_> = things
+        .iter()
+        .filter_map(|(trans, collider, boss)| {
+            let (color, size) = if boss {
+                (BOSS_COLOR, 10.0)
+            } else if collider.layer.intersects(Layers::ENEMY) {
+                (ENEMY_COLOR, 5.0)
+            } else if collider.layer.intersects(Layers::PICKUP) {
+                (PICKUP_COLOR, 3.0)
+            } else {
+                return None;
+            };
+            let position = map_position(trans.translation().truncate() - center)?;
+            Some((position, color, size))
+        })
+        .take(MAX_DOTS)
+        .collect();
+
+    let mut marks_left = marks.iter();
+    for (mut style, mut background) in &mut dots {
+        let Some((position, color, size)) = marks_left.next() else {
+            if style.display != Display::None {
+                style.display = Display::None;
+            }
+            continue;
+        };
+        style.display = Display::Flex;
+        style.width = Val::Px(*size);
+        style.height = Val::Px(*size);
+        style.left = Val::Px(position.x - size / 2.0);
+        style.top = Val::Px(position.y - size / 2.0);
+        background.0 = *color;
+    }
+    // Not enough dots yet, the new ones get used from the next frame
+    let missing = marks_left.count();
+    if missing == 0 {
+        return;
+    }
+    commands.entity(frame).with_children(|map| {
+        for _ in 0..missing {
+            let mut bundle = dot(Color::NONE, 0.0);
+            bundle.style.display = Display::None;
+            map.spawn((MinimapDot, bundle));
+        }
+    });
+}
+
+/// Where on the edge of a box of `half` size around the center an arrow toward `offset` goes,
+/// `None` when it is inside the box anyway
+fn edge_point(offset: Vec2, half: Vec2) -> Option<Vec2> {
+    if offset.x.abs() <= half.x && offset.y.abs() <= half.y {
+        return None;
+    }
+    let fit = (half / offset.abs()).min_element();
+    Some(offset * fit)
+}
+
+fn point_at_bosses(
+    mut commands: Commands,
+    bosses: Query<(Entity, &GlobalTransform), With<Boss>>,
+    mut arrows: Query<(Entity, &BossArrow, &mut Transform, &mut Visibility)>,
+    camera: Query<(&GlobalTransform, &OrthographicProjection), With<Camera>>,
+    window: Query<&Window>,
+) {
+    let mut pointed = HashSet::new();
+    for (entity, arrow, _, _) in &arrows {
+        if bosses.contains(arrow.0) {
+            pointed.insert(arrow.0);
+        } else {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+    for (boss, _) in &bosses {
+        if pointed.contains(&boss) {
+            continue;
+        }
+        commands.spawn((
+            Gc(MainState::Playing),
+            BossArrow(boss),
+            ShapeBundle {
+                path: GeometryBuilder::build_as(&shapes::Polygon {
+                    points: vec![
+                        Vec2::new(18.0, 0.0),
+                        Vec2::new(-10.0, 12.0),
+                        Vec2::new(-10.0, -12.0),
+                    ],
+                    closed: true,
+                }),
+                spatial: SpatialBundle {
+                    visibility: Visibility::Hidden,
+                    ..default()
+                },
+                ..default()
+            },
+            Fill::color(BOSS_COLOR),
+            Stroke::new(Color::BLACK, 2.0),
+            Name::new("Boss Arrow"),
+        ));
+    }
+
+    let (Ok((camera, projection)), Ok(window)) = (camera.get_single(), window.get_single()) else {
+        return;
+    };
+    let center = camera.translation().truncate();
+    let half = (Vec2::new(window.width(), window.height()) / 2.0 - ARROW_MARGIN).max(Vec2::ZERO)
+        * projection.scale;
+    for (_, arrow, mut trans, mut visibility) in &mut arrows {
+        let Ok((_, boss)) = bosses.get(arrow.0) else {
+            continue;
+        };
+        let offset = boss.translation().truncate() - center;
+        let Some(edge) = edge_point(offset, half) else {
+            *visibility = Visibility::Hidden;
+            continue;
+        };
+        *visibility = Visibility::Visible;
+        trans.translation = (center + edge).extend(ZIndex::Cursor.into());
+        trans.rotation = Quat::from_rotation_z(offset.to_angle());
+        // Stays the same size on screen however far the camera is zoomed out
+        trans.scale = Vec3::new(projection.scale, projection.scale, 1.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn things_land_around_the_middle() {
+        let middle = MAP_SIZE / 2.0;
+        assert_eq!(map_position(Vec2::ZERO), Some(Vec2::splat(middle)));
+        assert_eq!(
+            map_position(Vec2::new(MAP_RANGE, MAP_RANGE)),
+            Some(Vec2::new(MAP_SIZE, 0.0))
+        );
+        assert_eq!(map_position(Vec2::new(0.0, -MAP_RANGE - 1.0)), None);
+    }
+
+    #[test]
+    fn arrows_sit_on_the_edge() {
+        let half = Vec2::new(400.0, 300.0);
+        assert_eq!(edge_point(Vec2::new(100.0, 100.0), half), None);
+        assert_eq!(
+            edge_point(Vec2::new(800.0, 0.0), half),
+            Some(Vec2::new(400.0, 0.0))
+        );
+        assert_eq!(
+            edge_point(Vec2::new(400.0, -600.0), half),
+            Some(Vec2::new(200.0, -300.0))
+        );
+    }
+}
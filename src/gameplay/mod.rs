@@ -1,45 +1,94 @@
 use std::time::Duration;
 
+use bevy::ecs::system::SystemParam;
 use bevy::input::common_conditions::input_just_pressed;
 use bevy_prng::WyRand;
 use rand::Rng;
 
-use crate::node_editor::{NodeEventData, NodeOutputTrigger, SnarlContainer, WorldEvent};
+use self::animation::{AnimationController, AnimationCues};
+use self::bullet_pool::BulletPool;
+use self::enemies::EnemyFire;
+use self::feedback::DamageDealt;
+use crate::collision::{Collider, Collision, Layers};
+use crate::node_editor::{
+    EventBudgetUsage,
+    GraphOwner,
+    NodeEventData,
+    NodeGraph,
+    NodeOutputTrigger,
+    SnarlContainer,
+    WorldEvent,
+};
 use crate::prelude::*;
-use crate::{assets, MainState, PlayingState, ZIndex};
+use crate::sound::{PlaySound, Sound};
+use crate::world::Wall;
+use crate::{assets, world_running, MainState, PlayingState, ZIndex};
+
+mod animation;
+mod boss;
+mod bullet_pool;
+mod camera;
+mod enemies;
+mod feedback;
+mod hud;
+mod minimap;
+mod progression;
 
 const BULLET_SPEED: f32 = 500.0;
 const PLAYER_SPEED: f32 = 300.0;
-const CAMERA_DISTANCE: f32 = 200.0;
-const CAMERA_MAX_SPEED: f32 = 500.0;
-const CAMERA_ACCELERATION: f32 = 2000.0;
+const PLAYER_HALF_SIZE: Vec2 = Vec2::new(28.0, 60.0);
+const BULLET_RADIUS: f32 = 12.0;
+const PLAYER_HEALTH: f32 = 100.0;
+const ENEMY_BULLET_DAMAGE: f32 = 8.0;
 
 pub struct GamePlayPlugin;
 
 impl Plugin for GamePlayPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(OnEnter(MainState::Playing), (spawn_player, spawn_camera))
-            .init_resource::<CursorLocation>()
-            .add_systems(OnEnter(PlayingState::ShootyTime), set_cursor_visibility)
-            .add_systems(OnEnter(PlayingState::Editor), set_cursor_visibility)
-            .add_systems(
-                Update,
+        app.add_plugins((
+            animation::AnimationPlugin,
+            enemies::EnemyPlugin,
+            boss::BossPlugin,
+            progression::ProgressionPlugin,
+            hud::HudPlugin,
+            feedback::FeedbackPlugin,
+            camera::CameraPlugin,
+            minimap::MinimapPlugin,
+        ))
+        .add_systems(
+            OnEnter(MainState::Playing),
+            (spawn_player, bullet_pool::reset_bullet_pool),
+        )
+        .init_resource::<CursorLocation>()
+        .init_resource::<LastShot>()
+        .init_resource::<BulletPool>()
+        .add_systems(OnEnter(PlayingState::ShootyTime), set_cursor_visibility)
+        .add_systems(OnEnter(PlayingState::Editor), set_cursor_visibility)
+        .add_systems(OnEnter(PlayingState::LevelUp), set_cursor_visibility)
+        .add_systems(
+            Update,
+            (
+                // Parking has to happen first so a bullet is never reused in the same frame
+                (bullet_hits, do_timer_despawning, spawn_bullet).chain(),
+                (bounce_bullets, move_bullets).chain(),
+                bullet_pool::print_bullet_pool,
+            )
+                .run_if(world_running),
+        )
+        .add_systems(
+            Update,
+            (
+                (move_player, boss::keep_in_arena).chain(),
+                face_moving_direction,
+                update_cursor_location,
                 (
-                    spawn_bullet,
-                    move_bullets,
-                    do_timer_despawning,
-                    do_animation,
-                    (move_player, set_camera_speed, move_camera).chain(),
-                    set_player_animation,
-                    update_cursor_location,
-                    (
-                        shoot_action.run_if(input_just_pressed(MouseButton::Left)),
-                        move_custom_cursor,
-                    )
-                        .after(update_cursor_location),
+                    shoot_action.run_if(input_just_pressed(MouseButton::Left)),
+                    move_custom_cursor,
                 )
-                    .run_if(in_state(PlayingState::ShootyTime)),
-            );
+                    .after(update_cursor_location),
+            )
+                .run_if(in_state(PlayingState::ShootyTime)),
+        );
     }
 }
 
@@ -49,20 +98,38 @@ struct Player;
 #[derive(Component)]
 struct MovingDirection(Vec2);
 
-#[derive(Component)]
-struct Animation {
-    row: usize,
-    col_size: usize,
-    anim_lenth: usize,
-    timer: Timer,
+#[derive(Component, Debug)]
+struct Health {
+    current: f32,
+    max: f32,
+}
+
+impl Health {
+    const fn new(max: f32) -> Self {
+        Self { current: max, max }
+    }
+
+    /// Returns true when this was the hit that killed it
+    fn damage(&mut self, amount: f32) -> bool {
+        let alive = self.current > 0.0;
+        self.current = (self.current - amount).max(0.0);
+        alive && self.current <= 0.0
+    }
 }
 
-fn spawn_player(mut commands: Commands, assets: Res<assets::Player>) {
+fn spawn_player(
+    mut commands: Commands,
+    assets: Res<assets::Player>,
+    sheets: Res<Assets<assets::Aseprite>>,
+) {
+    let Some(sheet) = sheets.get(&assets.sprite) else {
+        return;
+    };
     commands.spawn((
         Gc(MainState::Playing),
         Player,
         SpriteBundle {
-            texture: assets.sprite.clone(),
+            texture: sheet.image().clone(),
             transform: Transform::from_scale(Vec3::new(4.0, 4.0, 1.0)).with_translation(Vec3::new(
                 0.0,
                 0.0,
@@ -71,16 +138,18 @@ fn spawn_player(mut commands: Commands, assets: Res<assets::Player>) {
             ..default()
         },
         TextureAtlas {
-            layout: assets.layout.clone(),
+            layout: sheet.layout().clone(),
             ..default()
         },
-        Animation {
-            row: 0,
-            col_size: 8,
-            anim_lenth: 8,
-            timer: Timer::new(Duration::from_millis(150), TimerMode::Repeating),
-        },
+        AnimationController::new(&animation::PLAYER_ANIMATIONS, Some(sheet)),
+        AnimationCues::default(),
         MovingDirection(Vec2::ZERO),
+        Health::new(PLAYER_HEALTH),
+        Collider::aabb(
+            PLAYER_HALF_SIZE,
+            Layers::PLAYER,
+            Layers::ENEMY | Layers::ENEMY_BULLET | Layers::PICKUP | Layers::WALL,
+        ),
         Name::new("Player"),
     ));
     commands.spawn((
@@ -107,20 +176,6 @@ fn move_custom_cursor(
     trans.translation = cursor_location.0.extend(ZIndex::Cursor.into());
 }
 
-fn do_animation(mut query: Query<(&mut Animation, &mut TextureAtlas)>, time: Res<Time>) {
-    for (mut animation, mut atlas) in &mut query {
-        let mut index = atlas
-            .index
-            // when the row is changed this could overlfow
-            .saturating_sub(animation.row * animation.col_size);
-        if animation.timer.tick(time.delta()).finished() {
-            index += 1;
-        }
-        index %= animation.anim_lenth;
-        atlas.index = index + animation.row * animation.col_size;
-    }
-}
-
 fn set_cursor_visibility(mut query: Query<&mut Window>, state: Res<State<PlayingState>>) {
     let Ok(mut window) = query.get_single_mut() else {
         return;
@@ -128,7 +183,7 @@ fn set_cursor_visibility(mut query: Query<&mut Window>, state: Res<State<Playing
 
     match **state {
         PlayingState::None => {}
-        PlayingState::Editor => {
+        PlayingState::Editor | PlayingState::LevelUp => {
             window.cursor.visible = true;
         }
         PlayingState::ShootyTime => {
@@ -160,31 +215,27 @@ fn move_player(
     moving.0 = dir;
 }
 
-fn set_player_animation(
-    mut query: Query<(&mut Animation, &mut Sprite, &MovingDirection), Changed<MovingDirection>>,
+fn face_moving_direction(
+    mut query: Query<(&mut Sprite, &MovingDirection), Changed<MovingDirection>>,
 ) {
-    let Ok((mut animation, mut sprite, moving)) = query.get_single_mut() else {
+    let Ok((mut sprite, moving)) = query.get_single_mut() else {
         return;
     };
 
-    if moving.0 == Vec2::ZERO {
-        animation.row = 0;
-        animation.anim_lenth = 8;
-    } else {
-        animation.row = 1;
-        animation.anim_lenth = 4;
-
-        if moving.0.x > 0. {
-            sprite.flip_x = true;
-        } else if moving.0.x < 0. {
-            sprite.flip_x = false;
-        }
+    if moving.0.x > 0. {
+        sprite.flip_x = true;
+    } else if moving.0.x < 0. {
+        sprite.flip_x = false;
     }
 }
 
 #[derive(Resource, Default)]
 struct CursorLocation(Vec2);
 
+/// The players most recent shot, to show how much of its event budget it has used
+#[derive(Resource, Default)]
+struct LastShot(Option<u32>);
+
 fn update_cursor_location(
     mut cursor_location: ResMut<CursorLocation>,
     query_window: Query<&Window>,
@@ -208,181 +259,346 @@ fn update_cursor_location(
 
 fn shoot_action(
     cursor_location: Res<CursorLocation>,
-    query_player: Query<&GlobalTransform, With<Player>>,
+    mut query_player: Query<(&GlobalTransform, &mut AnimationCues), With<Player>>,
     mut node_trigger: EventWriter<NodeOutputTrigger>,
     snarl: Res<SnarlContainer>,
+    mut usage: ResMut<EventBudgetUsage>,
+    mut last_shot: ResMut<LastShot>,
+    mut sounds: EventWriter<PlaySound>,
 ) {
-    let Ok(player_trans) = query_player.get_single() else {
+    let Ok((player_trans, mut cues)) = query_player.get_single_mut() else {
         return;
     };
+    cues.shooting = true;
+    sounds.send(PlaySound(Sound::Shoot));
     let loc = player_trans.translation().truncate();
     let dir = (cursor_location.0 - loc).try_normalize().unwrap_or(Vec2::X);
 
+    let shot = usage.new_shot();
+    last_shot.0 = Some(shot);
     let data = NodeEventData {
         loc: Some(loc),
         dir: Some(dir),
+        shot,
         ..default()
     };
     let event = NodeOutputTrigger {
         data,
+        owner: GraphOwner::Player,
         node: snarl.shoot_trigger,
         output_index: 0,
     };
     node_trigger.send(event);
 }
 
-#[derive(Component)]
-struct SourceNode(egui_snarl::NodeId);
+/// The node that spawned a bullet, it gets told when the bullet hits or despawns
+#[derive(Component, Clone, Copy)]
+struct SourceNode {
+    owner: GraphOwner,
+    node: egui_snarl::NodeId,
+}
+
+/// Who fired a bullet, decides what it can hit
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Team {
+    Player,
+    Enemy,
+}
+
+impl Team {
+    fn collider(self) -> Collider {
+        match self {
+            Self::Player => Collider::circle(
+                BULLET_RADIUS,
+                Layers::PLAYER_BULLET,
+                Layers::ENEMY | Layers::WALL,
+            ),
+            Self::Enemy => Collider::circle(
+                BULLET_RADIUS,
+                Layers::ENEMY_BULLET,
+                Layers::PLAYER | Layers::WALL,
+            ),
+        }
+    }
+
+    const fn color(self) -> Color {
+        match self {
+            Self::Player => Color::WHITE,
+            Self::Enemy => Color::rgb(1.0, 0.4, 0.4),
+        }
+    }
+}
 
 #[derive(Component)]
 struct Bullet {
     dir: Vec2,
     lifetime: Timer,
+    shot: u32,
+    depth: u32,
+}
+
+struct BulletSpawn {
+    loc: Vec2,
+    dir: Vec2,
+    team: Team,
+    source: Option<SourceNode>,
+    shot: u32,
+    depth: u32,
 }
 
 fn spawn_bullet(
     mut commands: Commands,
     mut events: EventReader<WorldEvent>,
+    mut enemy_fire: EventReader<EnemyFire>,
     mut rng: ResMut<GlobalEntropy<WyRand>>,
+    mut pool: ResMut<BulletPool>,
     assets: Res<assets::Bullet>,
+    sheets: Res<Assets<assets::Aseprite>>,
 ) {
-    for event in events.read() {
-        if let WorldEvent::SpawnBullet {
-            loc: Some(loc),
-            dir,
+    let Some(sheet) = sheets.get(&assets.sprite) else {
+        return;
+    };
+    let from_nodes = events.read().filter_map(|event| match event {
+        WorldEvent::SpawnBullet {
+            data:
+                NodeEventData {
+                    loc: Some(loc),
+                    dir,
+                    shot,
+                    depth,
+                    ..
+                },
+            owner,
             id,
-        } = event
-        {
-            let dir = dir.unwrap_or_else(|| {
+        } => Some(BulletSpawn {
+            loc: *loc,
+            dir: dir.unwrap_or_else(|| {
                 Vec2::from_angle(rng.gen_range(0.0..(std::f32::consts::PI * 2.)))
-            });
-
+            }),
+            team: match owner {
+                GraphOwner::Player => Team::Player,
+                GraphOwner::Entity(_) => Team::Enemy,
+            },
+            source: Some(SourceNode {
+                owner: *owner,
+                node: *id,
+            }),
+            shot: *shot,
+            depth: *depth,
+        }),
+        _ => None,
+    });
+    let from_enemies = enemy_fire.read().map(|fire| BulletSpawn {
+        loc: fire.loc,
+        dir: fire.dir,
+        team: Team::Enemy,
+        source: None,
+        shot: 0,
+        depth: 0,
+    });
+    let spawns: Vec<_> = from_nodes.chain(from_enemies).collect();
+
+    for spawn in spawns {
+        // Everything that differs between shots, reused bullets get these overwritten
+        let state = (
+            Bullet {
+                dir: spawn.dir,
+                lifetime: Timer::new(Duration::from_secs(1), TimerMode::Once),
+                shot: spawn.shot,
+                depth: spawn.depth,
+            },
+            Transform {
+                translation: spawn.loc.extend(ZIndex::Bullet.into()),
+                scale: Vec3::new(2.0, 2.0, 1.0),
+                rotation: Quat::from_rotation_z(spawn.dir.to_angle() - std::f32::consts::FRAC_PI_4),
+            },
+            Visibility::Visible,
+            Sprite {
+                flip_x: true,
+                color: spawn.team.color(),
+                ..default()
+            },
+            spawn.team.collider(),
+            TextureAtlas {
+                layout: sheet.layout().clone(),
+                index: 0,
+            },
+            AnimationController::new(&animation::BULLET_ANIMATIONS, Some(sheet)),
+        );
+
+        let mut entity = if let Some(entity) = pool.take() {
+            commands.entity(entity)
+        } else {
             commands.spawn((
                 Gc(MainState::Playing),
-                Bullet {
-                    dir,
-                    lifetime: Timer::new(Duration::from_secs(1), TimerMode::Once),
-                },
-                SourceNode(*id),
                 SpriteBundle {
-                    texture: assets.sprite.clone_weak(),
-                    transform: Transform {
-                        translation: loc.extend(ZIndex::Bullet.into()),
-                        scale: Vec3::new(2.0, 2.0, 1.0),
-                        rotation: Quat::from_rotation_z(
-                            dir.to_angle() - std::f32::consts::FRAC_PI_4,
-                        ),
-                    },
-                    sprite: Sprite {
-                        flip_x: true,
-                        ..default()
-                    },
+                    texture: sheet.image().clone_weak(),
                     ..default()
                 },
-                TextureAtlas {
-                    layout: assets.layout.clone(),
-                    index: 0,
-                },
-                Animation {
-                    row: 0,
-                    col_size: 4,
-                    anim_lenth: 4,
-                    timer: Timer::new(Duration::from_millis(100), TimerMode::Repeating),
-                },
                 Fill::color(Color::YELLOW),
                 Stroke::new(Color::YELLOW_GREEN, 1.5),
                 Name::new("Bullet"),
-            ));
-        }
+            ))
+        };
+        entity.insert(state);
+        match spawn.source {
+            Some(source) => entity.insert(source),
+            None => entity.remove::<SourceNode>(),
+        };
     }
 }
 
-fn move_bullets(mut query: Query<(&Bullet, &mut Transform)>, time: Res<Time>) {
-    for (bullet, mut trans) in &mut query {
+fn move_bullets(mut query: Query<(&Bullet, &Visibility, &mut Transform)>, time: Res<Time>) {
+    for (bullet, visibility, mut trans) in &mut query {
+        if visibility == Visibility::Hidden {
+            continue;
+        }
         trans.translation += bullet.dir.extend(0.0) * BULLET_SPEED * time.delta_seconds();
     }
 }
 
 fn do_timer_despawning(
     mut commands: Commands,
-    mut query: Query<(Entity, &mut Bullet, &SourceNode, &GlobalTransform)>,
+    mut query: Query<(
+        Entity,
+        &mut Bullet,
+        Option<&SourceNode>,
+        &Visibility,
+        &GlobalTransform,
+    )>,
     time: Res<Time>,
+    mut pool: ResMut<BulletPool>,
     mut events: EventWriter<NodeOutputTrigger>,
 ) {
-    for (entity_id, mut bullet, node, trans) in &mut query {
-        if bullet.lifetime.tick(time.delta()).finished() {
+    for (entity_id, mut bullet, node, visibility, trans) in &mut query {
+        if visibility == Visibility::Hidden {
+            continue;
+        }
+        if !bullet.lifetime.tick(time.delta()).finished() {
+            continue;
+        }
+        if let Some(node) = node {
             let data = NodeEventData {
                 loc: Some(trans.translation().truncate()),
+                shot: bullet.shot,
+                depth: bullet.depth,
                 ..default()
             };
             events.send(NodeOutputTrigger {
                 data,
-                node: node.0,
+                owner: node.owner,
+                node: node.node,
                 output_index: 1,
             });
-            commands.entity(entity_id).despawn_recursive();
         }
+        pool.park(&mut commands, entity_id);
     }
 }
 
-#[derive(Component)]
-struct CameraSpeed(Vec2);
-
-#[derive(Component)]
-struct CameraRelative(Vec2);
-
-fn spawn_camera(mut commands: Commands) {
-    commands
-        .spawn(Camera2dBundle::default())
-        .insert(CameraSpeed(Vec2::ZERO))
-        .insert(CameraRelative(Vec2::ZERO));
+/// Everything that can come out of something getting hit
+#[derive(SystemParam)]
+struct HitEvents<'w> {
+    triggers: EventWriter<'w, NodeOutputTrigger>,
+    sounds: EventWriter<'w, PlaySound>,
+    damage: EventWriter<'w, DamageDealt>,
 }
 
-fn move_camera(
-    mut camera: Query<(&mut Transform, &mut CameraRelative, &CameraSpeed), Without<Player>>,
-    player: Query<&Transform, With<Player>>,
-    time: Res<Time>,
+fn bullet_hits(
+    mut commands: Commands,
+    mut collisions: EventReader<Collision>,
+    bullets: Query<(&Bullet, Option<&SourceNode>, &Visibility, &GlobalTransform)>,
+    graphs: Query<(), With<NodeGraph>>,
+    mut targets: Query<&mut Health>,
+    mut pool: ResMut<BulletPool>,
+    mut hits: HitEvents,
 ) {
-    let Ok(player_trans) = player.get_single() else {
-        return;
-    };
+    let mut hit = bevy::utils::HashSet::new();
+    for collision in collisions.read() {
+        let Some((bullet_id, target)) = collision
+            .between(Layers::PLAYER_BULLET, Layers::ENEMY)
+            .or_else(|| collision.between(Layers::ENEMY_BULLET, Layers::PLAYER))
+        else {
+            continue;
+        };
+        // A bullet can touch more than one target in the same frame, only the first one counts
+        if !hit.insert(bullet_id) {
+            continue;
+        }
+        let Ok((bullet, node, visibility, trans)) = bullets.get(bullet_id) else {
+            continue;
+        };
+        // Parked earlier this frame, it isn't really there anymore
+        if visibility == Visibility::Hidden {
+            continue;
+        }
+        // The graph of an enemy that died is gone with it, nothing would run the trigger
+        let node = node.filter(|node| match node.owner {
+            GraphOwner::Player => true,
+            GraphOwner::Entity(owner) => graphs.contains(owner),
+        });
 
-    for (mut trans, mut relative, speed) in &mut camera {
-        relative.0 += speed.0 * time.delta_seconds();
-        trans.translation =
-            (player_trans.translation.truncate() + relative.0).extend(trans.translation.z);
+        if let Some(node) = node {
+            let data = NodeEventData {
+                loc: Some(trans.translation().truncate()),
+                dir: Some(bullet.dir),
+                target: Some(target),
+                shot: bullet.shot,
+                depth: bullet.depth,
+            };
+            hits.triggers.send(NodeOutputTrigger {
+                data,
+                owner: node.owner,
+                node: node.node,
+                output_index: 0,
+            });
+        } else if let Ok(mut health) = targets.get_mut(target) {
+            // Bullets fired without a graph, or whose graph is gone, just do flat damage
+            if health.current > 0.0 {
+                hits.damage.send(DamageDealt {
+                    target,
+                    loc: trans.translation().truncate(),
+                    amount: ENEMY_BULLET_DAMAGE,
+                    crit: false,
+                });
+            }
+            health.damage(ENEMY_BULLET_DAMAGE);
+        }
+        hits.sounds.send(PlaySound(Sound::Hit));
+        pool.park(&mut commands, bullet_id);
     }
 }
 
-fn set_camera_speed(
-    mut camera: Query<(&CameraRelative, &mut CameraSpeed)>,
-    player: Query<&MovingDirection, With<Player>>,
-    time: Res<Time>,
+fn bounce_bullets(
+    mut collisions: EventReader<Collision>,
+    mut bullets: Query<(&mut Bullet, &mut Transform, &Collider)>,
+    walls: Query<(&GlobalTransform, &Collider), With<Wall>>,
 ) {
-    let Ok(player_moving) = player.get_single() else {
-        return;
-    };
-    let Ok((camera_trans, mut camera_speed)) = camera.get_single_mut() else {
-        return;
-    };
-
-    let target = player_moving.0 * CAMERA_DISTANCE;
-    let delta = target - camera_trans.0;
-
-    if delta.length() < 50.0 {
-        let ideal_speed = delta.length() / 2.0;
-        let factor = camera_speed.0.length() / ideal_speed;
-
-        if factor.is_normal() {
-            camera_speed.0 /= 1. + factor * time.delta_seconds();
-            return;
+    for collision in collisions.read() {
+        let Some((bullet_id, wall)) =
+            collision.between(Layers::PLAYER_BULLET | Layers::ENEMY_BULLET, Layers::WALL)
+        else {
+            continue;
+        };
+        let (Ok((mut bullet, mut trans, collider)), Ok((wall_trans, wall_collider))) =
+            (bullets.get_mut(bullet_id), walls.get(wall))
+        else {
+            continue;
+        };
+        let push = collider.shape.separation(
+            trans.translation.truncate(),
+            wall_collider.shape,
+            wall_trans.translation().truncate(),
+        );
+        let Some(normal) = push.try_normalize() else {
+            continue;
+        };
+        trans.translation += push.extend(0.0);
+        // Only flip bullets still heading into the wall, or they would get stuck bouncing
+        let into_wall = bullet.dir.dot(normal);
+        if into_wall < 0.0 {
+            bullet.dir -= 2.0 * into_wall * normal;
+            trans.rotation =
+                Quat::from_rotation_z(bullet.dir.to_angle() - std::f32::consts::FRAC_PI_4);
         }
     }
-
-    let delta = delta.normalize_or_zero();
-
-    camera_speed.0 =
-        Vec2::from_angle(delta.to_angle()).normalize_or_zero() * camera_speed.0.length();
-    camera_speed.0 += delta * CAMERA_ACCELERATION * time.delta_seconds();
-    camera_speed.0 = camera_speed.0.clamp_length_max(CAMERA_MAX_SPEED);
 }
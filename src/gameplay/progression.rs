@@ -0,0 +1,203 @@
+//! Experience dropped by enemies, leveling up and picking which node to get more of.
+
+use bevy::utils::HashSet;
+use bevy_egui::{egui, EguiContexts};
+use bevy_prng::WyRand;
+use rand::seq::IteratorRandom;
+
+use super::enemies::EnemyDied;
+use super::Player;
+use crate::collision::{Collider, Collision, Layers};
+use crate::node_editor::{Node, NodeInventory, PALETTE};
+use crate::prelude::*;
+use crate::{world_running, MainState, PlayingState, ZIndex};
+
+const ORB_RADIUS: f32 = 6.0;
+/// Orbs closer than this fly to the player
+const MAGNET_RADIUS: f32 = 200.0;
+const MAGNET_SPEED: f32 = 450.0;
+const CHOICES: usize = 3;
+/// Copies of the picked node a level up gives
+const REWARD: u32 = 2;
+
+pub struct ProgressionPlugin;
+
+impl Plugin for ProgressionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Experience>()
+            .init_resource::<LevelUpChoices>()
+            .add_systems(OnEnter(MainState::Playing), reset_experience)
+            .add_systems(OnEnter(PlayingState::LevelUp), roll_choices)
+            .add_systems(
+                Update,
+                (
+                    (drop_orbs, magnetize_orbs, collect_orbs).run_if(world_running),
+                    // Waits for the editor to close before popping up
+                    start_level_up
+                        .after(collect_orbs)
+                        .run_if(in_state(PlayingState::ShootyTime)),
+                ),
+            )
+            .add_systems(
+                Update,
+                level_up_screen.run_if(in_state(PlayingState::LevelUp)),
+            );
+    }
+}
+
+#[derive(Resource, Default, Debug)]
+pub(super) struct Experience {
+    pub(super) level: u32,
+    pub(super) xp: u32,
+    /// Level ups that haven't been picked a reward for yet
+    pub(super) pending: u32,
+}
+
+impl Experience {
+    pub(super) const fn needed(&self) -> u32 {
+        10 + self.level * 5
+    }
+
+    const fn gain(&mut self, xp: u32) {
+        self.xp += xp;
+        while self.xp >= self.needed() {
+            self.xp -= self.needed();
+            self.level += 1;
+            self.pending += 1;
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+struct LevelUpChoices(Vec<Node>);
+
+#[derive(Component)]
+struct Orb(u32);
+
+fn reset_experience(mut commands: Commands) {
+    commands.insert_resource(Experience::default());
+}
+
+fn drop_orbs(mut commands: Commands, mut died: EventReader<EnemyDied>) {
+    for event in died.read() {
+        commands.spawn((
+            Gc(MainState::Playing),
+            Orb(event.xp),
+            ShapeBundle {
+                path: GeometryBuilder::build_as(&shapes::Circle {
+                    radius: ORB_RADIUS,
+                    center: Vec2::ZERO,
+                }),
+                spatial: SpatialBundle::from_transform(Transform::from_translation(
+                    event.loc.extend(ZIndex::Enemy.into()),
+                )),
+                ..default()
+            },
+            Fill::color(Color::LIME_GREEN),
+            Collider::circle(ORB_RADIUS, Layers::PICKUP, Layers::PLAYER),
+            Name::new("Orb"),
+        ));
+    }
+}
+
+fn magnetize_orbs(
+    mut orbs: Query<&mut Transform, (With<Orb>, Without<Player>)>,
+    player: Query<&Transform, With<Player>>,
+    time: Res<Time>,
+) {
+    let Ok(player) = player.get_single() else {
+        return;
+    };
+    let player = player.translation.truncate();
+    for mut trans in &mut orbs {
+        let to_player = player - trans.translation.truncate();
+        let distance = to_player.length();
+        if distance > MAGNET_RADIUS {
+            continue;
+        }
+        // Speeds up the closer it gets, so orbs never trail behind a moving player
+        let speed = MAGNET_SPEED * (2.0 - distance / MAGNET_RADIUS);
+        let step = to_player.clamp_length_max(speed * time.delta_seconds());
+        trans.translation += step.extend(0.0);
+    }
+}
+
+fn collect_orbs(
+    mut commands: Commands,
+    mut collisions: EventReader<Collision>,
+    orbs: Query<&Orb>,
+    mut experience: ResMut<Experience>,
+) {
+    let mut collected = HashSet::new();
+    for collision in collisions.read() {
+        let Some((orb, _)) = collision.between(Layers::PICKUP, Layers::PLAYER) else {
+            continue;
+        };
+        let Ok(xp) = orbs.get(orb) else {
+            continue;
+        };
+        if collected.insert(orb) {
+            experience.gain(xp.0);
+            commands.entity(orb).despawn_recursive();
+        }
+    }
+}
+
+fn start_level_up(experience: Res<Experience>, mut next: ResMut<NextState<PlayingState>>) {
+    if experience.pending > 0 {
+        next.set(PlayingState::LevelUp);
+    }
+}
+
+fn roll_choices(mut choices: ResMut<LevelUpChoices>, mut rng: ResMut<GlobalEntropy<WyRand>>) {
+    choices.0 = PALETTE.into_iter().choose_multiple(rng.as_mut(), CHOICES);
+}
+
+fn level_up_screen(
+    mut ctx: EguiContexts,
+    choices: Res<LevelUpChoices>,
+    mut inventory: ResMut<NodeInventory>,
+    mut experience: ResMut<Experience>,
+    mut next: ResMut<NextState<PlayingState>>,
+) {
+    egui::Window::new(format!("Level {}!", experience.level))
+        .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+        .collapsible(false)
+        .resizable(false)
+        .show(ctx.ctx_mut(), |ui| {
+            ui.label("Pick a node to add to your inventory");
+            ui.horizontal(|ui| {
+                for node in &choices.0 {
+                    let label = format!(
+                        "{REWARD}x {} ({} owned)",
+                        node.title(),
+                        inventory.count(node)
+                    );
+                    if ui.button(label).clicked() {
+                        inventory.add(node, REWARD);
+                        experience.pending = experience.pending.saturating_sub(1);
+                        next.set(PlayingState::ShootyTime);
+                    }
+                }
+            });
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gaining_experience_levels_up() {
+        let mut experience = Experience::default();
+        experience.gain(9);
+        assert_eq!((experience.level, experience.pending), (0, 0));
+
+        // Enough for two levels at once, 10 then 15
+        experience.gain(17);
+        assert_eq!(
+            (experience.level, experience.xp, experience.pending),
+            (2, 1, 2)
+        );
+    }
+}
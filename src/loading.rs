@@ -0,0 +1,198 @@
+//! The screens shown while assets load, and when they fail to.
+
+use bevy::asset::UntypedAssetLoadFailedEvent;
+use bevy_asset_loader::loading_state::LoadingStateSet;
+use iyes_progress::{ProgressCounter, ProgressPlugin};
+
+use crate::prelude::*;
+use crate::MainState;
+
+pub struct LoadingPlugin;
+
+impl Plugin for LoadingPlugin {
+    fn build(&self, app: &mut App) {
+        // The loading state moves on by itself, this only counts how far along it is
+        app.add_plugins(ProgressPlugin::new(MainState::Loading))
+            .init_resource::<LoadFailures>()
+            .add_systems(OnEnter(MainState::Loading), spawn_loading_screen)
+            .add_systems(
+                Update,
+                update_progress
+                    .after(LoadingStateSet(MainState::Loading))
+                    .run_if(in_state(MainState::Loading)),
+            )
+            // Failures can come in right as the state changes, so this always listens
+            .add_systems(Update, collect_failures)
+            .add_systems(OnEnter(MainState::Error), spawn_error_screen)
+            .add_systems(
+                Update,
+                update_error_text
+                    .run_if(in_state(MainState::Error).and_then(resource_changed::<LoadFailures>)),
+            );
+    }
+}
+
+#[derive(Resource, Default, Debug)]
+struct LoadFailures(Vec<String>);
+
+#[derive(Component)]
+struct ProgressFill;
+
+#[derive(Component)]
+struct ProgressText;
+
+#[derive(Component)]
+struct ErrorText;
+
+fn spawn_loading_screen(mut commands: Commands) {
+    commands.spawn((Gc(MainState::Loading), Camera2dBundle::default()));
+    commands
+        .spawn((
+            Gc(MainState::Loading),
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    row_gap: Val::Px(12.0),
+                    ..default()
+                },
+                ..default()
+            },
+            Name::new("Loading Screen"),
+        ))
+        .with_children(|screen| {
+            screen.spawn((
+                ProgressText,
+                TextBundle::from_section(
+                    "Loading",
+                    TextStyle {
+                        font_size: 32.0,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                ),
+            ));
+            screen
+                .spawn(NodeBundle {
+                    style: Style {
+                        width: Val::Percent(40.0),
+                        height: Val::Px(16.0),
+                        ..default()
+                    },
+                    background_color: Color::rgb(0.15, 0.15, 0.2).into(),
+                    ..default()
+                })
+                .with_children(|background| {
+                    background.spawn((
+                        ProgressFill,
+                        NodeBundle {
+                            style: Style {
+                                width: Val::Percent(0.0),
+                                height: Val::Percent(100.0),
+                                ..default()
+                            },
+                            background_color: Color::rgb(0.4, 0.8, 0.5).into(),
+                            ..default()
+                        },
+                    ));
+                });
+        });
+}
+
+fn update_progress(
+    counter: Option<Res<ProgressCounter>>,
+    mut fills: Query<&mut Style, With<ProgressFill>>,
+    mut texts: Query<&mut Text, With<ProgressText>>,
+) {
+    let progress = counter
+        .map(|counter| counter.progress())
+        .unwrap_or_default();
+    let (loaded, total) = (progress.done, progress.total);
+    let fraction = if total == 0 {
+        0.0
+    } else {
+        loaded as f32 / total as f32
+    };
+
+    for mut style in &mut fills {
+        style.width = Val::Percent(fraction * 100.0);
+    }
+    for mut text in &mut texts {
+        text.sections[0].value = format!("Loading {loaded}/{total}");
+    }
+}
+
+fn collect_failures(
+    mut events: EventReader<UntypedAssetLoadFailedEvent>,
+    mut failures: ResMut<LoadFailures>,
+) {
+    for event in events.read() {
+        failures.0.push(format!("{}: {}", event.path, event.error));
+    }
+}
+
+fn spawn_error_screen(mut commands: Commands) {
+    commands.spawn((Gc(MainState::Error), Camera2dBundle::default()));
+    commands
+        .spawn((
+            Gc(MainState::Error),
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    row_gap: Val::Px(16.0),
+                    padding: UiRect::all(Val::Px(32.0)),
+                    ..default()
+                },
+                background_color: Color::rgb(0.15, 0.02, 0.02).into(),
+                ..default()
+            },
+            Name::new("Error Screen"),
+        ))
+        .with_children(|screen| {
+            screen.spawn(TextBundle::from_section(
+                "Some assets failed to load",
+                TextStyle {
+                    font_size: 40.0,
+                    color: Color::rgb(1.0, 0.4, 0.4),
+                    ..default()
+                },
+            ));
+            screen.spawn((
+                ErrorText,
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 20.0,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                ),
+            ));
+            screen.spawn(TextBundle::from_section(
+                "Press Esc to quit",
+                TextStyle {
+                    font_size: 20.0,
+                    color: Color::GRAY,
+                    ..default()
+                },
+            ));
+        });
+}
+
+fn update_error_text(failures: Res<LoadFailures>, mut texts: Query<&mut Text, With<ErrorText>>) {
+    let message = if failures.0.is_empty() {
+        String::from("No error was reported, check the log")
+    } else {
+        failures.0.join("\n")
+    };
+    for mut text in &mut texts {
+        text.sections[0].value.clone_from(&message);
+    }
+}
@@ -40,8 +40,12 @@
 
 mod assets;
 mod background;
+mod collision;
 mod gameplay;
+mod loading;
 mod node_editor;
+mod sound;
+mod world;
 
 #[allow(unused_imports)]
 mod prelude {
@@ -59,6 +63,8 @@ pub enum MainState {
     #[default]
     Loading,
     Playing,
+    /// Some assets failed to load, shows what went wrong
+    Error,
 }
 
 #[derive(States, Default, Clone, Hash, Eq, PartialEq, Debug)]
@@ -67,13 +73,46 @@ pub enum PlayingState {
     None,
     ShootyTime,
     Editor,
+    /// Picking a reward after leveling up, the game is paused
+    LevelUp,
 }
 
+/// Lets the world keep going in slow motion while the editor is open
+#[derive(Resource, Debug)]
+pub struct EditorTime {
+    pub live: bool,
+    /// How fast the world runs while editing, as a fraction of normal speed
+    pub speed: f32,
+}
+
+impl Default for EditorTime {
+    fn default() -> Self {
+        Self {
+            live: false,
+            speed: 0.2,
+        }
+    }
+}
+
+/// Run condition for everything that moves the world along, as opposed to player input
+fn world_running(state: Res<State<PlayingState>>, editor: Option<Res<EditorTime>>) -> bool {
+    match **state {
+        PlayingState::ShootyTime => true,
+        PlayingState::Editor => editor.is_some_and(|editor| editor.live),
+        PlayingState::None | PlayingState::LevelUp => false,
+    }
+}
+
+#[derive(Clone, Copy)]
 #[repr(u32)]
 enum ZIndex {
     Background,
+    Dust,
+    Wall,
+    Enemy,
     Bullet,
     Player,
+    Decals,
     Cursor,
 }
 
@@ -101,6 +140,7 @@ fn main() {
         app.add_plugins(bevy_inspector_egui::quick::WorldInspectorPlugin::new());
     }
 
+    app.init_resource::<EditorTime>();
     app.init_state::<MainState>();
     app.init_state::<PlayingState>();
     app.add_substate(MainState::Playing, PlayingState::ShootyTime);
@@ -109,9 +149,13 @@ fn main() {
 
     app.add_plugins((
         assets::AssetPlugin,
+        loading::LoadingPlugin,
         node_editor::NodeEditorPlugin,
         gameplay::GamePlayPlugin,
         background::BackgroundPlugin,
+        collision::CollisionPlugin,
+        world::WorldPlugin,
+        sound::SoundPlugin,
     ));
 
     app.add_systems(
@@ -130,5 +174,6 @@ fn swap_mode(state: Res<State<PlayingState>>, mut next: ResMut<NextState<Playing
         PlayingState::None => PlayingState::None,
         PlayingState::ShootyTime => PlayingState::Editor,
         PlayingState::Editor => PlayingState::ShootyTime,
+        PlayingState::LevelUp => PlayingState::LevelUp,
     });
 }
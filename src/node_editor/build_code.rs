@@ -0,0 +1,229 @@
+//! Compact copy-pasteable codes for sharing graphs.
+//!
+//! A code is url-safe base64 of a small binary format, ending in a checksum so typos and
+//! truncated pastes are caught before they replace anyones graph.
+
+use std::fmt;
+
+use base64::Engine;
+use bevy_egui::egui;
+
+use super::{would_loop, Node, SnarlContainer};
+
+const VERSION: u8 = 1;
+const ENGINE: base64::engine::GeneralPurpose = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildCodeError {
+    NotBase64,
+    BadChecksum,
+    UnknownVersion(u8),
+    Truncated,
+    InvalidGraph(String),
+}
+
+impl fmt::Display for BuildCodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotBase64 => write!(f, "that is not a build code"),
+            Self::BadChecksum => write!(f, "the build code is damaged, was all of it copied?"),
+            Self::UnknownVersion(version) => {
+                write!(f, "the build code is from an unknown version ({version})")
+            }
+            Self::Truncated => write!(f, "the build code is cut short"),
+            Self::InvalidGraph(reason) => {
+                write!(f, "the build code has an invalid graph: {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for BuildCodeError {}
+
+/// 32 bit FNV-1a, plenty to catch copy paste mistakes
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ u32::from(*byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
+const fn node_kind(node: &Node) -> u8 {
+    match node {
+        Node::OnShoot => 0,
+        Node::SpawnBullet => 1,
+        Node::DealDmg => 2,
+        Node::Spread { .. } => 3,
+        Node::Repeating => 4,
+        Node::Explosion => 5,
+        // Macros are expanded before encoding, so this never ends up in a code
+        Node::Macro { .. } => u8::MAX,
+    }
+}
+
+pub fn encode(container: &SnarlContainer) -> String {
+    let snarl = &container.snarl;
+    let mut nodes: Vec<_> = snarl.nodes_pos_ids().collect();
+    nodes.sort_by_key(|(id, _, _)| *id);
+    let index_of = |id: egui_snarl::NodeId| {
+        nodes
+            .iter()
+            .position(|(node, _, _)| *node == id)
+            .unwrap_or_default() as u16
+    };
+
+    let mut bytes = vec![VERSION];
+    bytes.extend((nodes.len() as u16).to_le_bytes());
+    for (_, pos, node) in &nodes {
+        bytes.push(node_kind(node));
+        if let Node::Spread { count, angle } = node {
+            // The DSL and editor both stop at `MAX_SPREAD`, so this always fits
+            bytes.push(u8::try_from(*count).unwrap_or(u8::MAX));
+            bytes.extend(angle.to_le_bytes());
+        }
+        bytes.extend((pos.x.round() as i16).to_le_bytes());
+        bytes.extend((pos.y.round() as i16).to_le_bytes());
+    }
+    bytes.extend(index_of(container.shoot_trigger).to_le_bytes());
+
+    let mut wires = Vec::new();
+    for (id, _, node) in &nodes {
+        for output in 0..node.outputs() {
+            let pin = snarl.out_pin(egui_snarl::OutPinId { node: *id, output });
+            for remote in pin.remotes {
+                wires.push((index_of(*id), output as u8, index_of(remote.node)));
+            }
+        }
+    }
+    bytes.extend((wires.len() as u16).to_le_bytes());
+    for (from, output, to) in wires {
+        bytes.extend(from.to_le_bytes());
+        bytes.push(output);
+        bytes.extend(to.to_le_bytes());
+    }
+
+    bytes.extend(checksum(&bytes).to_le_bytes());
+    ENGINE.encode(bytes)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    const fn take<const N: usize>(&mut self) -> Result<[u8; N], BuildCodeError> {
+        let Some((taken, rest)) = self.bytes.split_first_chunk::<N>() else {
+            return Err(BuildCodeError::Truncated);
+        };
+        self.bytes = rest;
+        Ok(*taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, BuildCodeError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, BuildCodeError> {
+        Ok(u16::from_le_bytes(self.take()?))
+    }
+
+    fn i16(&mut self) -> Result<i16, BuildCodeError> {
+        Ok(i16::from_le_bytes(self.take()?))
+    }
+
+    fn f32(&mut self) -> Result<f32, BuildCodeError> {
+        Ok(f32::from_le_bytes(self.take()?))
+    }
+}
+
+fn invalid(reason: &str) -> BuildCodeError {
+    BuildCodeError::InvalidGraph(reason.to_owned())
+}
+
+/// Decodes a build code, checking that the graph in it is one the editor could have made
+pub fn decode(code: &str) -> Result<SnarlContainer, BuildCodeError> {
+    let bytes = ENGINE
+        .decode(code.trim())
+        .map_err(|_| BuildCodeError::NotBase64)?;
+    let Some((body, sum)) = bytes.split_last_chunk::<4>() else {
+        return Err(BuildCodeError::Truncated);
+    };
+    if checksum(body) != u32::from_le_bytes(*sum) {
+        return Err(BuildCodeError::BadChecksum);
+    }
+
+    let mut reader = Reader { bytes: body };
+    let version = reader.u8()?;
+    if version != VERSION {
+        return Err(BuildCodeError::UnknownVersion(version));
+    }
+
+    let mut snarl = egui_snarl::Snarl::new();
+    let mut ids = Vec::new();
+    for _ in 0..reader.u16()? {
+        let node = match reader.u8()? {
+            0 => Node::OnShoot,
+            1 => Node::SpawnBullet,
+            2 => Node::DealDmg,
+            3 => {
+                let count = u32::from(reader.u8()?).max(1);
+                let angle = reader.f32()?;
+                if !angle.is_finite() {
+                    return Err(invalid("spread angle is not a number"));
+                }
+                Node::Spread { count, angle }
+            }
+            4 => Node::Repeating,
+            5 => Node::Explosion,
+            _ => return Err(invalid("unknown node type")),
+        };
+        let pos = egui::Pos2::new(f32::from(reader.i16()?), f32::from(reader.i16()?));
+        ids.push(snarl.insert_node(pos, node));
+    }
+    let node_at = |index: u16| {
+        ids.get(usize::from(index))
+            .copied()
+            .ok_or_else(|| invalid("wire to a missing node"))
+    };
+
+    let shoot_trigger = node_at(reader.u16()?)?;
+    if snarl.get_node(shoot_trigger) != Some(&Node::OnShoot) {
+        return Err(invalid("the shoot trigger is not an on shoot node"));
+    }
+    // The editor only ever has the one, any others could never fire
+    if snarl
+        .node_ids()
+        .any(|(id, node)| id != shoot_trigger && *node == Node::OnShoot)
+    {
+        return Err(invalid("more than one on shoot node"));
+    }
+
+    for _ in 0..reader.u16()? {
+        let from = node_at(reader.u16()?)?;
+        let output = usize::from(reader.u8()?);
+        let to = node_at(reader.u16()?)?;
+
+        if snarl.get_node(from).map_or(0, Node::outputs) <= output {
+            return Err(invalid("wire from a missing output"));
+        }
+        if snarl.get_node(to).map_or(0, Node::inputs) == 0 {
+            return Err(invalid("wire into a node without inputs"));
+        }
+        let input = egui_snarl::InPinId { node: to, input: 0 };
+        if !snarl.in_pin(input).remotes.is_empty() {
+            return Err(invalid("two wires into the same input"));
+        }
+        if would_loop(&snarl, from, to) {
+            return Err(invalid("the graph loops"));
+        }
+        snarl.connect(egui_snarl::OutPinId { node: from, output }, input);
+    }
+
+    if !reader.bytes.is_empty() {
+        return Err(invalid("trailing data"));
+    }
+
+    Ok(SnarlContainer {
+        snarl,
+        shoot_trigger,
+    })
+}
@@ -0,0 +1,505 @@
+//! A small text format for writing node graphs by hand.
+//!
+//! ```text
+//! on_shoot -> spread(5, 30deg) -> bullet: spawn_bullet.hit -> deal_dmg
+//! bullet.despawned -> explosion
+//! ```
+//!
+//! Each line (or `;` separated statement) is a chain of nodes joined by `->`.
+//! `name: node` labels a node so later chains can continue from it,
+//! and `.output` picks which output the following arrow leaves from.
+//! Anything after a `#` is a comment.
+
+use std::fmt;
+
+use bevy::utils::HashMap;
+use bevy_egui::egui;
+
+use super::{Node, SnarlContainer, MAX_SPREAD};
+
+const COLUMN_WIDTH: f32 = 200.0;
+const ROW_HEIGHT: f32 = 120.0;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DslError {
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for DslError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {line}: {}", self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for DslError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number { value: f32, unit: String },
+    Arrow,
+    Dot,
+    Colon,
+    Comma,
+    Open,
+    Close,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(&char) = chars.peek() {
+        match char {
+            ' ' | '\t' | '\r' => {
+                chars.next();
+            }
+            '-' if take_arrow(&mut chars) => tokens.push(Token::Arrow),
+            '.' => {
+                chars.next();
+                tokens.push(Token::Dot);
+            }
+            ':' => {
+                chars.next();
+                tokens.push(Token::Colon);
+            }
+            ',' => {
+                chars.next();
+                tokens.push(Token::Comma);
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '-' | '0'..='9' => {
+                let mut number = String::new();
+                number.push(char);
+                chars.next();
+                while let Some(&next) = chars.peek() {
+                    if next.is_ascii_digit() || (next == '.' && !number.contains('.')) {
+                        number.push(next);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                let mut unit = String::new();
+                while let Some(&next) = chars.peek() {
+                    if next.is_ascii_alphabetic() {
+                        unit.push(next);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                let value = number
+                    .parse()
+                    .map_err(|_| format!("`{number}` is not a number"))?;
+                tokens.push(Token::Number { value, unit });
+            }
+            char if char.is_ascii_alphabetic() || char == '_' => {
+                let mut ident = String::new();
+                while let Some(&next) = chars.peek() {
+                    if next.is_ascii_alphanumeric() || next == '_' {
+                        ident.push(next);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Ident(ident));
+            }
+            char => return Err(format!("unexpected `{char}`")),
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Consumes a `->` if that is what comes next
+fn take_arrow(chars: &mut std::iter::Peekable<std::str::Chars>) -> bool {
+    let mut lookahead = chars.clone();
+    lookahead.next();
+    if lookahead.next() == Some('>') {
+        chars.next();
+        chars.next();
+        true
+    } else {
+        false
+    }
+}
+
+fn node_from_name(name: &str, args: &[(f32, String)]) -> Result<Option<Node>, String> {
+    let node = match name {
+        "on_shoot" => Node::OnShoot,
+        "spawn_bullet" => Node::SpawnBullet,
+        "deal_dmg" => Node::DealDmg,
+        "repeat" => Node::Repeating,
+        "explosion" => Node::Explosion,
+        "spread" => {
+            if args.len() > 2 {
+                return Err(String::from("`spread` takes at most a count and an angle"));
+            }
+            let mut node = Node::spread();
+            let Node::Spread { count, angle } = &mut node else {
+                return Ok(None);
+            };
+            if let Some((value, unit)) = args.first() {
+                if !unit.is_empty()
+                    || value.fract() != 0.0
+                    || *value < 1.0
+                    || *value > MAX_SPREAD as f32
+                {
+                    return Err(format!("`{value}{unit}` is not a valid bullet count"));
+                }
+                *count = *value as u32;
+            }
+            if let Some((value, unit)) = args.get(1) {
+                *angle = match unit.as_str() {
+                    "" | "deg" => value.to_radians(),
+                    "rad" => *value,
+                    unit => return Err(format!("unknown angle unit `{unit}`")),
+                };
+            }
+            return Ok(Some(node));
+        }
+        _ => return Ok(None),
+    };
+
+    if args.is_empty() {
+        Ok(Some(node))
+    } else {
+        Err(format!("`{name}` does not take any arguments"))
+    }
+}
+
+fn node_name(node: &Node) -> String {
+    match node {
+        Node::OnShoot => String::from("on_shoot"),
+        Node::SpawnBullet => String::from("spawn_bullet"),
+        Node::DealDmg => String::from("deal_dmg"),
+        Node::Repeating => String::from("repeat"),
+        Node::Explosion => String::from("explosion"),
+        // Macros are expanded before exporting, see `MacroLibrary::expand_container`
+        Node::Macro { id, .. } => format!("macro_{id}"),
+        Node::Spread { count, angle } => {
+            // Degrees read better, but only if they come back as the exact same angle
+            let degrees = (angle.to_degrees() * 1000.0).round() / 1000.0;
+            if degrees.to_radians().to_bits() == angle.to_bits() {
+                format!("spread({count}, {degrees}deg)")
+            } else {
+                format!("spread({count}, {angle}rad)")
+            }
+        }
+    }
+}
+
+fn output_name(node: &Node, output: usize) -> String {
+    node.output_label(output).to_lowercase()
+}
+
+/// A node reference in a chain, along with the output the chain continues from
+struct Step {
+    node: usize,
+    output: Option<usize>,
+}
+
+#[derive(Default)]
+struct Builder {
+    nodes: Vec<Node>,
+    parents: Vec<Option<usize>>,
+    wires: Vec<(usize, usize, usize)>,
+    labels: HashMap<String, usize>,
+}
+
+impl Builder {
+    fn chain(&mut self, text: &str) -> Result<(), String> {
+        let tokens = tokenize(text)?;
+        if tokens.is_empty() {
+            return Ok(());
+        }
+
+        let mut steps = Vec::new();
+        for step in tokens.split(|token| *token == Token::Arrow) {
+            steps.push(self.step(step)?);
+        }
+
+        for pair in steps.windows(2) {
+            let [from, to] = pair else {
+                continue;
+            };
+            self.connect(from, to.node)?;
+        }
+
+        if let Some(Step {
+            output: Some(_), ..
+        }) = steps.last()
+        {
+            return Err(String::from(
+                "the chain ends on an output that leads nowhere",
+            ));
+        }
+        Ok(())
+    }
+
+    fn step(&mut self, tokens: &[Token]) -> Result<Step, String> {
+        let (label, tokens) = match tokens {
+            [Token::Ident(label), Token::Colon, rest @ ..] => (Some(label), rest),
+            tokens => (None, tokens),
+        };
+        let Some((Token::Ident(name), mut rest)) = tokens.split_first() else {
+            return Err(String::from("expected a node"));
+        };
+
+        let mut args = Vec::new();
+        let mut has_args = false;
+        if let Some((Token::Open, after)) = rest.split_first() {
+            has_args = true;
+            let Some(close) = after.iter().position(|token| *token == Token::Close) else {
+                return Err(String::from("missing `)`"));
+            };
+            for arg in after[..close].split(|token| *token == Token::Comma) {
+                match arg {
+                    [Token::Number { value, unit }] => args.push((*value, unit.clone())),
+                    [] if close == 0 => {}
+                    _ => return Err(format!("bad arguments to `{name}`")),
+                }
+            }
+            rest = &after[close + 1..];
+        }
+
+        let node = if let Some(node) = node_from_name(name, &args)? {
+            let index = self.nodes.len();
+            self.nodes.push(node);
+            self.parents.push(None);
+            if let Some(label) = label {
+                if node_from_name(label, &[]).ok().flatten().is_some() {
+                    return Err(format!(
+                        "`{label}` is a node name and can't be used as a label"
+                    ));
+                }
+                if self.labels.insert(label.clone(), index).is_some() {
+                    return Err(format!("`{label}` is already defined"));
+                }
+            }
+            index
+        } else if let Some(&index) = self.labels.get(name) {
+            if label.is_some() || has_args {
+                return Err(format!("`{name}` is a label, not a node"));
+            }
+            index
+        } else {
+            return Err(format!("unknown node `{name}`"));
+        };
+
+        let output = match rest {
+            [] => None,
+            [Token::Dot, Token::Ident(output)] => {
+                let node_kind = &self.nodes[node];
+                let index = (0..node_kind.outputs())
+                    .find(|index| output_name(node_kind, *index) == *output)
+                    .ok_or_else(|| format!("`{name}` has no output called `{output}`"))?;
+                Some(index)
+            }
+            [Token::Dot, Token::Number { value, unit }]
+                if unit.is_empty()
+                    && value.fract() == 0.0
+                    && (*value as usize) < self.nodes[node].outputs() =>
+            {
+                Some(*value as usize)
+            }
+            _ => return Err(format!("unexpected tokens after `{name}`")),
+        };
+
+        Ok(Step { node, output })
+    }
+
+    fn connect(&mut self, from: &Step, to: usize) -> Result<(), String> {
+        if self.nodes[to].inputs() == 0 {
+            return Err(format!("`{}` has no input", node_name(&self.nodes[to])));
+        }
+        if self.parents[to].is_some() {
+            return Err(format!(
+                "`{}` is already connected to something",
+                node_name(&self.nodes[to])
+            ));
+        }
+
+        let mut ancestor = Some(from.node);
+        while let Some(node) = ancestor {
+            if node == to {
+                return Err(String::from("this would create a loop"));
+            }
+            ancestor = self.parents[node];
+        }
+
+        self.parents[to] = Some(from.node);
+        self.wires.push((from.node, from.output.unwrap_or(0), to));
+        Ok(())
+    }
+
+    /// Lays the nodes out in columns by how deep they are in the graph
+    fn positions(&self) -> Vec<egui::Pos2> {
+        let mut rows = HashMap::<usize, usize>::new();
+        (0..self.nodes.len())
+            .map(|node| {
+                let mut depth = 0;
+                let mut ancestor = self.parents[node];
+                while let Some(parent) = ancestor {
+                    depth += 1;
+                    ancestor = self.parents[parent];
+                }
+                let row = rows.entry(depth).or_default();
+                *row += 1;
+                egui::Pos2::new(depth as f32 * COLUMN_WIDTH, (*row - 1) as f32 * ROW_HEIGHT)
+            })
+            .collect()
+    }
+
+    fn finish(self) -> Result<SnarlContainer, DslError> {
+        let Some(shoot) = self.nodes.iter().position(|node| *node == Node::OnShoot) else {
+            return Err(DslError {
+                line: None,
+                message: String::from("the graph needs an `on_shoot` node"),
+            });
+        };
+
+        let mut snarl = egui_snarl::Snarl::new();
+        let ids: Vec<_> = self
+            .nodes
+            .iter()
+            .zip(self.positions())
+            .map(|(node, pos)| snarl.insert_node(pos, *node))
+            .collect();
+        for (from, output, to) in self.wires {
+            snarl.connect(
+                egui_snarl::OutPinId {
+                    node: ids[from],
+                    output,
+                },
+                egui_snarl::InPinId {
+                    node: ids[to],
+                    input: 0,
+                },
+            );
+        }
+
+        Ok(SnarlContainer {
+            snarl,
+            shoot_trigger: ids[shoot],
+        })
+    }
+}
+
+/// Builds a graph from its text form
+pub fn parse(text: &str) -> Result<SnarlContainer, DslError> {
+    let mut builder = Builder::default();
+    for (index, line) in text.lines().enumerate() {
+        let code = line.split('#').next().unwrap_or_default();
+        for statement in code.split(';') {
+            builder.chain(statement).map_err(|message| DslError {
+                line: Some(index + 1),
+                message,
+            })?;
+        }
+    }
+    builder.finish()
+}
+
+/// Turns a graph back into text that [`parse`] understands
+pub fn export(container: &SnarlContainer) -> String {
+    let snarl = &container.snarl;
+    let mut ids: Vec<_> = snarl.node_ids().map(|(id, _)| id).collect();
+    ids.sort();
+
+    let children = |id: egui_snarl::NodeId| {
+        let Some(node) = snarl.get_node(id) else {
+            return Vec::new();
+        };
+        let mut children = Vec::new();
+        for output in 0..node.outputs() {
+            let mut remotes = snarl
+                .out_pin(egui_snarl::OutPinId { node: id, output })
+                .remotes;
+            remotes.sort();
+            children.extend(remotes.into_iter().map(|remote| (output, remote.node)));
+        }
+        children
+    };
+
+    // Nodes that branch need a label so the other branches can start from them
+    let mut labels = HashMap::new();
+    let mut label_counts = HashMap::<String, usize>::new();
+    let mut has_parent = bevy::utils::HashSet::new();
+    for &id in &ids {
+        let wires = children(id);
+        has_parent.extend(wires.iter().map(|(_, child)| *child));
+        if wires.len() > 1 {
+            let Some(node) = snarl.get_node(id) else {
+                continue;
+            };
+            let base = node_name(node)
+                .split('(')
+                .next()
+                .unwrap_or_default()
+                .to_owned();
+            let count = label_counts.entry(base.clone()).or_default();
+            *count += 1;
+            labels.insert(id, format!("{base}_{count}"));
+        }
+    }
+
+    let define = |id: egui_snarl::NodeId| {
+        let name = snarl.get_node(id).map(node_name).unwrap_or_default();
+        match labels.get(&id) {
+            Some(label) => format!("{label}: {name}"),
+            None => name,
+        }
+    };
+    let output_suffix = |id: egui_snarl::NodeId, output: usize| match snarl.get_node(id) {
+        Some(node) if node.outputs() > 1 => format!(".{}", output_name(node, output)),
+        _ => String::new(),
+    };
+
+    let mut lines = Vec::new();
+    for root in ids.iter().filter(|id| !has_parent.contains(*id)) {
+        let mut pending = std::collections::VecDeque::from([(*root, None)]);
+        while let Some((start, branch)) = pending.pop_front() {
+            let mut current = start;
+            let mut steps = match branch {
+                None => vec![define(start)],
+                Some((output, child)) => {
+                    let label = labels.get(&start).cloned().unwrap_or_default();
+                    current = child;
+                    vec![label + &output_suffix(start, output), define(child)]
+                }
+            };
+
+            loop {
+                let wires = children(current);
+                let Some(&(output, child)) = wires.first() else {
+                    break;
+                };
+                pending.extend(wires[1..].iter().map(|&wire| (current, Some(wire))));
+                if let Some(step) = steps.last_mut() {
+                    step.push_str(&output_suffix(current, output));
+                }
+                steps.push(define(child));
+                current = child;
+            }
+            lines.push(steps.join(" -> "));
+        }
+    }
+
+    let mut text = lines.join("\n");
+    text.push('\n');
+    text
+}
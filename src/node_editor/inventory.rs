@@ -0,0 +1,98 @@
+//! How many of each node the player has left to place, the editor only lets you place what
+//! you own and gives nodes back when they are removed.
+
+use super::Node;
+use crate::prelude::*;
+
+/// Every node the player can place by hand, the shoot trigger comes with the graph
+pub const PALETTE: [Node; 5] = [
+    Node::SpawnBullet,
+    Node::DealDmg,
+    Node::spread(),
+    Node::Repeating,
+    Node::Explosion,
+];
+
+fn palette_index(node: &Node) -> Option<usize> {
+    PALETTE
+        .iter()
+        .position(|entry| std::mem::discriminant(entry) == std::mem::discriminant(node))
+}
+
+/// Counts per palette entry, nodes outside the palette are free
+fn tally(nodes: &[Node]) -> [u32; PALETTE.len()] {
+    let mut counts = [0; PALETTE.len()];
+    for index in nodes.iter().filter_map(palette_index) {
+        counts[index] += 1;
+    }
+    counts
+}
+
+/// Nodes owned but not placed in the graph
+#[derive(Resource, Debug)]
+pub struct NodeInventory {
+    owned: [u32; PALETTE.len()],
+}
+
+impl Default for NodeInventory {
+    fn default() -> Self {
+        let mut inventory = Self {
+            owned: [0; PALETTE.len()],
+        };
+        inventory.add(&Node::SpawnBullet, 1);
+        inventory
+    }
+}
+
+impl NodeInventory {
+    pub fn count(&self, node: &Node) -> u32 {
+        palette_index(node).map_or(0, |index| self.owned[index])
+    }
+
+    pub fn add(&mut self, node: &Node, amount: u32) {
+        if let Some(index) = palette_index(node) {
+            self.owned[index] += amount;
+        }
+    }
+
+    pub fn can_take(&self, nodes: &[Node]) -> bool {
+        tally(nodes)
+            .iter()
+            .zip(&self.owned)
+            .all(|(needed, owned)| needed <= owned)
+    }
+
+    /// Takes all of the nodes out of the inventory, or none of them if some are missing
+    pub fn take(&mut self, nodes: &[Node]) -> Result<(), String> {
+        let needed = tally(nodes);
+        if let Some(index) = (0..PALETTE.len()).find(|&index| needed[index] > self.owned[index]) {
+            return Err(format!(
+                "needs {} {} but you only have {}",
+                needed[index],
+                PALETTE[index].title(),
+                self.owned[index]
+            ));
+        }
+        for (owned, needed) in self.owned.iter_mut().zip(needed) {
+            *owned -= needed;
+        }
+        Ok(())
+    }
+
+    pub fn give_back(&mut self, nodes: &[Node]) {
+        for (owned, returned) in self.owned.iter_mut().zip(tally(nodes)) {
+            *owned += returned;
+        }
+    }
+
+    /// Trades the nodes of a graph being replaced for the nodes of its replacement
+    pub fn swap(&mut self, old: &[Node], new: &[Node]) -> Result<(), String> {
+        self.give_back(old);
+        if let Err(err) = self.take(new) {
+            // Can't fail, these were just given back
+            let _ = self.take(old);
+            return Err(err);
+        }
+        Ok(())
+    }
+}
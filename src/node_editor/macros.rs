@@ -0,0 +1,380 @@
+//! User defined macro nodes, groups of nodes collapsed into a single reusable node.
+//!
+//! Macros only exist in the editor, before running a graph every macro node is expanded back
+//! into the nodes it was made from. Nodes outside of macros keep their ids while doing so.
+
+use bevy::utils::{HashMap, HashSet};
+use bevy_egui::egui;
+use egui_snarl::{InPinId, NodeId, OutPinId, Snarl};
+
+use super::{Node, SnarlContainer};
+use crate::prelude::*;
+
+/// Macros can contain macros made before them, this caps how deep we go expanding them
+const MAX_NESTING: usize = 32;
+
+pub struct MacroDef {
+    name: String,
+    snarl: Snarl<Node>,
+    /// Inner pins wired to the macro nodes inputs, in order
+    inputs: Vec<InPinId>,
+    /// Inner pins wired to the macro nodes outputs, in order
+    outputs: Vec<OutPinId>,
+}
+
+impl MacroDef {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn pin_label(&self, node: NodeId, label: &str) -> String {
+        let title = self.snarl.get_node(node).map_or("", Node::title);
+        if label.is_empty() {
+            title.to_owned()
+        } else {
+            format!("{title}: {label}")
+        }
+    }
+
+    pub fn input_label(&self, input: usize) -> String {
+        self.inputs.get(input).map_or_else(String::new, |pin| {
+            let label = self.snarl.get_node(pin.node).map_or("", Node::input_label);
+            self.pin_label(pin.node, label)
+        })
+    }
+
+    pub fn output_label(&self, output: usize) -> String {
+        self.outputs.get(output).map_or_else(String::new, |pin| {
+            let label = self
+                .snarl
+                .get_node(pin.node)
+                .map_or("", |node| node.output_label(pin.output));
+            self.pin_label(pin.node, label)
+        })
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct MacroLibrary {
+    macros: Vec<MacroDef>,
+}
+
+/// An outside node both fed by and feeding the selection would end up wired to the macro node
+/// both ways, a loop
+fn check_no_detours(snarl: &Snarl<Node>, selection: &HashSet<NodeId>) -> Result<(), String> {
+    let downstream = outside_reach(snarl, selection, true);
+    let upstream = outside_reach(snarl, selection, false);
+    if downstream.intersection(&upstream).next().is_some() {
+        return Err(String::from(
+            "nodes outside the selection sit between selected nodes, select them too",
+        ));
+    }
+    Ok(())
+}
+
+/// Nodes outside the selection reachable from it, following wires forward or backward
+fn outside_reach(
+    snarl: &Snarl<Node>,
+    selection: &HashSet<NodeId>,
+    forward: bool,
+) -> HashSet<NodeId> {
+    let mut reached = HashSet::new();
+    let mut stack: Vec<_> = selection.iter().copied().collect();
+    while let Some(id) = stack.pop() {
+        let Some(node) = snarl.get_node(id) else {
+            continue;
+        };
+        let next: Vec<_> = if forward {
+            (0..node.outputs())
+                .flat_map(|output| snarl.out_pin(OutPinId { node: id, output }).remotes)
+                .map(|remote| remote.node)
+                .collect()
+        } else {
+            (0..node.inputs())
+                .flat_map(|input| snarl.in_pin(InPinId { node: id, input }).remotes)
+                .map(|remote| remote.node)
+                .collect()
+        };
+        for next in next {
+            if !selection.contains(&next) && reached.insert(next) {
+                stack.push(next);
+            }
+        }
+    }
+    reached
+}
+
+impl MacroLibrary {
+    pub fn get(&self, id: usize) -> Option<&MacroDef> {
+        self.macros.get(id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &MacroDef)> {
+        self.macros.iter().enumerate()
+    }
+
+    /// A fresh node for placing the macro in a graph
+    pub fn node(&self, id: usize) -> Option<Node> {
+        self.get(id).map(|def| Node::Macro {
+            id,
+            inputs: def.inputs.len(),
+            outputs: def.outputs.len(),
+        })
+    }
+
+    /// Every plain node placing the macro would take
+    pub fn contents(&self, id: usize) -> Vec<Node> {
+        let Some(node) = self.node(id) else {
+            return Vec::new();
+        };
+        let mut snarl = Snarl::new();
+        snarl.insert_node(egui::Pos2::ZERO, node);
+        self.expand(&snarl).nodes().copied().collect()
+    }
+
+    /// The plain nodes a graph is made of, with macros expanded
+    pub fn graph_contents(&self, snarl: &Snarl<Node>) -> Vec<Node> {
+        self.expand(snarl).nodes().copied().collect()
+    }
+
+    /// Moves the selected nodes out of the graph into a new macro, putting a macro node in
+    /// their place that is wired up the same way
+    pub fn collapse(
+        &mut self,
+        name: String,
+        snarl: &mut Snarl<Node>,
+        selection: &HashSet<NodeId>,
+    ) -> Result<NodeId, String> {
+        let selected: Vec<_> = snarl
+            .nodes_pos_ids()
+            .filter(|(id, _, _)| selection.contains(id))
+            .map(|(id, pos, node)| (id, pos, *node))
+            .collect();
+        if selected.is_empty() {
+            return Err(String::from("select some nodes first"));
+        }
+        if selected.iter().any(|(_, _, node)| *node == Node::OnShoot) {
+            return Err(String::from("on shoot nodes can't go in a macro"));
+        }
+        check_no_detours(snarl, selection)?;
+
+        let origin = selected.iter().fold(
+            egui::Pos2::new(f32::INFINITY, f32::INFINITY),
+            |min, (_, pos, _)| min.min(*pos),
+        );
+
+        let mut inner = Snarl::new();
+        let ids: HashMap<_, _> = selected
+            .iter()
+            .map(|(id, pos, node)| (*id, inner.insert_node(*pos - origin.to_vec2(), *node)))
+            .collect();
+
+        let mut inputs = Vec::new();
+        let mut outer_inputs = Vec::new();
+        let mut outputs = Vec::new();
+        let mut outer_outputs = Vec::new();
+        for (id, _, node) in &selected {
+            for input in 0..node.inputs() {
+                let pin = snarl.in_pin(InPinId { node: *id, input });
+                if pin
+                    .remotes
+                    .iter()
+                    .any(|remote| selection.contains(&remote.node))
+                {
+                    continue;
+                }
+                inputs.push(InPinId {
+                    node: ids[id],
+                    input,
+                });
+                outer_inputs.push(pin.remotes);
+            }
+            for output in 0..node.outputs() {
+                let pin = snarl.out_pin(OutPinId { node: *id, output });
+                for remote in &pin.remotes {
+                    if let Some(&to) = ids.get(&remote.node) {
+                        inner.connect(
+                            OutPinId {
+                                node: ids[id],
+                                output,
+                            },
+                            InPinId {
+                                node: to,
+                                input: remote.input,
+                            },
+                        );
+                    }
+                }
+                let wired_inside = pin
+                    .remotes
+                    .iter()
+                    .any(|remote| selection.contains(&remote.node));
+                let outside: Vec<_> = pin
+                    .remotes
+                    .into_iter()
+                    .filter(|remote| !selection.contains(&remote.node))
+                    .collect();
+                // Outputs only used inside the macro stay hidden in it
+                if wired_inside && outside.is_empty() {
+                    continue;
+                }
+                outputs.push(OutPinId {
+                    node: ids[id],
+                    output,
+                });
+                outer_outputs.push(outside);
+            }
+        }
+
+        for (id, _, _) in &selected {
+            snarl.remove_node(*id);
+        }
+
+        let id = self.macros.len();
+        let macro_node = snarl.insert_node(
+            origin,
+            Node::Macro {
+                id,
+                inputs: inputs.len(),
+                outputs: outputs.len(),
+            },
+        );
+        connect_outer(snarl, macro_node, outer_inputs, outer_outputs);
+
+        self.macros.push(MacroDef {
+            name,
+            snarl: inner,
+            inputs,
+            outputs,
+        });
+        Ok(macro_node)
+    }
+
+    /// Replaces every macro node in the graph with the nodes it stands for
+    pub fn expand(&self, snarl: &Snarl<Node>) -> Snarl<Node> {
+        let mut snarl = snarl.clone();
+        for _ in 0..MAX_NESTING {
+            let macros: Vec<_> = snarl
+                .nodes_pos_ids()
+                .filter_map(|(node, pos, kind)| match kind {
+                    Node::Macro { id, .. } => Some((node, pos, *id)),
+                    _ => None,
+                })
+                .collect();
+            if macros.is_empty() {
+                break;
+            }
+            for (node, pos, id) in macros {
+                self.expand_node(&mut snarl, node, pos, id);
+            }
+        }
+        snarl
+    }
+
+    pub fn expand_container(&self, container: &SnarlContainer) -> SnarlContainer {
+        SnarlContainer {
+            snarl: self.expand(&container.snarl),
+            shoot_trigger: container.shoot_trigger,
+        }
+    }
+
+    fn expand_node(&self, snarl: &mut Snarl<Node>, node: NodeId, pos: egui::Pos2, id: usize) {
+        let Some(kind) = snarl.get_node(node).copied() else {
+            return;
+        };
+        let inputs: Vec<_> = (0..kind.inputs())
+            .map(|input| snarl.in_pin(InPinId { node, input }).remotes)
+            .collect();
+        let outputs: Vec<_> = (0..kind.outputs())
+            .map(|output| snarl.out_pin(OutPinId { node, output }).remotes)
+            .collect();
+        snarl.remove_node(node);
+
+        let Some(def) = self.get(id) else {
+            bevy::log::warn!("Macro {id} is missing from the library");
+            return;
+        };
+
+        let ids: HashMap<_, _> = def
+            .snarl
+            .nodes_pos_ids()
+            .map(|(inner, inner_pos, kind)| {
+                (inner, snarl.insert_node(pos + inner_pos.to_vec2(), *kind))
+            })
+            .collect();
+        for (inner, kind) in def.snarl.node_ids() {
+            for output in 0..kind.outputs() {
+                let pin = def.snarl.out_pin(OutPinId {
+                    node: inner,
+                    output,
+                });
+                for remote in pin.remotes {
+                    snarl.connect(
+                        OutPinId {
+                            node: ids[&inner],
+                            output,
+                        },
+                        InPinId {
+                            node: ids[&remote.node],
+                            input: remote.input,
+                        },
+                    );
+                }
+            }
+        }
+
+        for (remotes, pin) in inputs.into_iter().zip(&def.inputs) {
+            for remote in remotes {
+                snarl.connect(
+                    remote,
+                    InPinId {
+                        node: ids[&pin.node],
+                        input: pin.input,
+                    },
+                );
+            }
+        }
+        for (remotes, pin) in outputs.into_iter().zip(&def.outputs) {
+            for remote in remotes {
+                snarl.connect(
+                    OutPinId {
+                        node: ids[&pin.node],
+                        output: pin.output,
+                    },
+                    remote,
+                );
+            }
+        }
+    }
+}
+
+/// Wires a freshly made macro node up to the pins that used to lead into its nodes
+fn connect_outer(
+    snarl: &mut Snarl<Node>,
+    macro_node: NodeId,
+    outer_inputs: Vec<Vec<OutPinId>>,
+    outer_outputs: Vec<Vec<InPinId>>,
+) {
+    for (input, remotes) in outer_inputs.into_iter().enumerate() {
+        for remote in remotes {
+            snarl.connect(
+                remote,
+                InPinId {
+                    node: macro_node,
+                    input,
+                },
+            );
+        }
+    }
+    for (output, remotes) in outer_outputs.into_iter().enumerate() {
+        for remote in remotes {
+            snarl.connect(
+                OutPinId {
+                    node: macro_node,
+                    output,
+                },
+                remote,
+            );
+        }
+    }
+}
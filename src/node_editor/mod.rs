@@ -1,7 +1,21 @@
+use bevy::ecs::system::SystemParam;
+use bevy::utils::{HashMap, HashSet};
 use bevy_egui::{egui, EguiContexts, EguiPlugin};
 
 use crate::prelude::*;
-use crate::PlayingState;
+use crate::sound::{PlaySound, Sound};
+use crate::{world_running, EditorTime, PlayingState};
+
+mod build_code;
+mod dsl;
+mod inventory;
+mod macros;
+mod patterns;
+
+pub use dsl::DslError;
+pub use inventory::{NodeInventory, PALETTE};
+pub use macros::MacroLibrary;
+pub use patterns::EnemyPatterns;
 
 pub struct NodeEditorPlugin;
 
@@ -12,26 +26,222 @@ impl Plugin for NodeEditorPlugin {
         {
             app.add_plugins(EguiPlugin);
         }
+        app.add_plugins(NodeRuntimePlugin)
+            .init_resource::<NodeInventory>()
+            .init_resource::<GraphName>()
+            .add_systems(
+                Update,
+                (node_editor, pattern_editor).run_if(in_state(PlayingState::Editor)),
+            )
+            .add_systems(
+                Update,
+                set_editor_time_speed.run_if(
+                    in_state(PlayingState::Editor).and_then(resource_changed::<EditorTime>),
+                ),
+            )
+            .add_systems(OnEnter(PlayingState::Editor), set_editor_time_speed)
+            .add_systems(OnExit(PlayingState::Editor), reset_time_speed);
+    }
+}
+
+fn set_editor_time_speed(editor: Res<EditorTime>, mut time: ResMut<Time<Virtual>>) {
+    time.set_relative_speed(if editor.live { editor.speed } else { 1.0 });
+}
+
+fn reset_time_speed(mut time: ResMut<Time<Virtual>>) {
+    time.set_relative_speed(1.0);
+}
+
+/// Runs the node graphs without any of the egui side, so it can be used headless
+pub struct NodeRuntimePlugin;
+
+impl Plugin for NodeRuntimePlugin {
+    fn build(&self, app: &mut App) {
         app.init_resource::<SnarlContainer>()
+            .init_resource::<MacroLibrary>()
+            .init_resource::<EnemyPatterns>()
+            .init_resource::<RuntimeGraph>()
+            .init_resource::<EventBudget>()
+            .init_resource::<EventBudgetUsage>()
             .add_event::<NodeOutputTrigger>()
             .add_event::<NodeTrigger>()
             .add_event::<WorldEvent>()
-            .add_systems(Update, node_editor.run_if(in_state(PlayingState::Editor)))
             .add_systems(
                 Update,
-                (do_world_events, activate_nodes).run_if(in_state(PlayingState::ShootyTime)),
+                compile_graph.before(NodeRuntimeSet).run_if(
+                    resource_changed::<SnarlContainer>.or_else(resource_changed::<MacroLibrary>),
+                ),
+            )
+            .add_systems(
+                Update,
+                (activate_nodes, do_world_events)
+                    .chain()
+                    .in_set(NodeRuntimeSet)
+                    .run_if(world_running),
             );
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NodeRuntimeSet;
+
+/// The graph the runtime actually runs, with every macro expanded
+#[derive(Resource, Default)]
+pub struct RuntimeGraph {
+    pub snarl: egui_snarl::Snarl<Node>,
+}
+
+/// Which graph an event belongs to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum GraphOwner {
+    /// The players graph, the one in the editor
+    #[default]
+    Player,
+    /// An entity running its own [`NodeGraph`]
+    Entity(Entity),
+}
+
+/// A graph owned by an entity, like an enemy attack pattern
+#[derive(Component)]
+pub struct NodeGraph {
+    pub snarl: egui_snarl::Snarl<Node>,
+    pub trigger: egui_snarl::NodeId,
+}
+
+impl NodeGraph {
+    /// Patterns are written in the same text format the editor exports
+    pub fn from_dsl(text: &str) -> Result<Self, DslError> {
+        let container = dsl::parse(text)?;
+        Ok(Self {
+            snarl: container.snarl,
+            trigger: container.shoot_trigger,
+        })
+    }
+}
+
+fn graph_for<'a>(
+    owner: GraphOwner,
+    runtime: &'a RuntimeGraph,
+    graphs: &'a Query<&NodeGraph>,
+) -> Option<&'a egui_snarl::Snarl<Node>> {
+    match owner {
+        GraphOwner::Player => Some(&runtime.snarl),
+        GraphOwner::Entity(entity) => graphs.get(entity).ok().map(|graph| &graph.snarl),
+    }
+}
+
+fn compile_graph(
+    container: Res<SnarlContainer>,
+    library: Res<MacroLibrary>,
+    mut runtime: ResMut<RuntimeGraph>,
+) {
+    runtime.snarl = library.expand(&container.snarl);
+}
+
+/// Most events a spread node can fan out into, build codes store the count in a byte
+pub const MAX_SPREAD: u32 = u8::MAX as u32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Node {
     OnShoot,
     SpawnBullet,
     DealDmg,
-    Spread,
+    /// Fans the event out into `count` events, spread over `angle` radians
+    Spread {
+        count: u32,
+        angle: f32,
+    },
     Repeating,
     Explosion,
+    /// A node from the [`MacroLibrary`], expanded before the graph runs
+    Macro {
+        id: usize,
+        inputs: usize,
+        outputs: usize,
+    },
+}
+
+impl Node {
+    pub const fn spread() -> Self {
+        Self::Spread {
+            count: 3,
+            angle: std::f32::consts::FRAC_PI_6,
+        }
+    }
+
+    pub const fn title(&self) -> &'static str {
+        match self {
+            Self::OnShoot => "On shoot",
+            Self::SpawnBullet => "Spawn Bullet",
+            Self::Explosion => "Spawn Explosion",
+            Self::Repeating => "Repeat",
+            Self::DealDmg => "Dmg",
+            Self::Spread { .. } => "Spread",
+            Self::Macro { .. } => "Macro",
+        }
+    }
+
+    pub const fn outputs(&self) -> usize {
+        match self {
+            Self::Macro { outputs, .. } => *outputs,
+            Self::SpawnBullet => 2,
+            Self::Explosion
+            | Self::Repeating
+            | Self::DealDmg
+            | Self::OnShoot
+            | Self::Spread { .. } => 1,
+        }
+    }
+
+    pub const fn inputs(&self) -> usize {
+        match self {
+            Self::Macro { inputs, .. } => *inputs,
+            Self::OnShoot => 0,
+            Self::SpawnBullet
+            | Self::Explosion
+            | Self::Repeating
+            | Self::DealDmg
+            | Self::Spread { .. } => 1,
+        }
+    }
+
+    pub const fn output_label(&self, output: usize) -> &'static str {
+        match self {
+            Self::SpawnBullet => ["Hit", "Despawned"][output],
+            Self::OnShoot | Self::Explosion => "Hit",
+            Self::Repeating => "Event",
+            Self::DealDmg => "Fatal",
+            Self::Spread { .. } | Self::Macro { .. } => "",
+        }
+    }
+
+    pub const fn input_label(&self) -> &'static str {
+        match self {
+            Self::OnShoot | Self::Spread { .. } | Self::Macro { .. } => "",
+            Self::SpawnBullet | Self::Explosion => "Spawn",
+            Self::Repeating => "Event",
+            Self::DealDmg => "Target",
+        }
+    }
+}
+
+/// Directions for each event a spread node sends out
+fn spread_directions(dir: Option<Vec2>, count: u32, angle: f32) -> Vec<Vec2> {
+    let Some(dir) = dir else {
+        // Without a direction we just go all the way around
+        let step = std::f32::consts::TAU / count as f32;
+        return (0..count)
+            .map(|index| Vec2::from_angle(index as f32 * step))
+            .collect();
+    };
+    if count <= 1 {
+        return vec![dir];
+    }
+
+    let step = angle / (count - 1) as f32;
+    (0..count)
+        .map(|index| Vec2::from_angle(index as f32 * step - angle / 2.).rotate(dir))
+        .collect()
 }
 
 #[derive(Clone, Debug, Default)]
@@ -39,11 +249,93 @@ pub struct NodeEventData {
     pub loc: Option<Vec2>,
     pub dir: Option<Vec2>,
     pub target: Option<Entity>,
+    /// The shot this event chain started from, see [`EventBudgetUsage::new_shot`]
+    pub shot: u32,
+    /// How many node hops this event is away from the shot
+    pub depth: u32,
 }
 
-#[derive(Event, Debug)]
+/// Limits on how many node events a graph is allowed to produce,
+/// anything over these is dropped to stop feedback loops from freezing the game.
+#[derive(Resource, Debug, Clone)]
+pub struct EventBudget {
+    pub per_frame: usize,
+    pub per_shot: usize,
+    pub max_depth: u32,
+}
+
+impl Default for EventBudget {
+    fn default() -> Self {
+        Self {
+            per_frame: 512,
+            per_shot: 1024,
+            max_depth: 32,
+        }
+    }
+}
+
+/// Seconds a shot can go without producing events before we stop tracking it
+const SHOT_EXPIRY: f32 = 10.0;
+
+#[derive(Debug)]
+struct ShotUsage {
+    events: usize,
+    last_active: f32,
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct EventBudgetUsage {
+    next_shot: u32,
+    now: f32,
+    frame: usize,
+    shots: HashMap<u32, ShotUsage>,
+    dropped: usize,
+}
+
+impl EventBudgetUsage {
+    pub const fn new_shot(&mut self) -> u32 {
+        let shot = self.next_shot;
+        self.next_shot = self.next_shot.wrapping_add(1);
+        shot
+    }
+
+    /// Events a shot has produced so far, zero once it has gone quiet long enough to be forgotten
+    pub fn spent(&self, shot: u32) -> usize {
+        self.shots.get(&shot).map_or(0, |usage| usage.events)
+    }
+
+    fn start_frame(&mut self, now: f32) {
+        self.now = now;
+        self.frame = 0;
+        self.shots
+            .retain(|_, usage| now - usage.last_active < SHOT_EXPIRY);
+    }
+
+    fn try_spend(&mut self, budget: &EventBudget, data: &NodeEventData) -> bool {
+        let shot = self.shots.entry(data.shot).or_insert(ShotUsage {
+            events: 0,
+            last_active: self.now,
+        });
+        shot.last_active = self.now;
+
+        if data.depth > budget.max_depth
+            || self.frame >= budget.per_frame
+            || shot.events >= budget.per_shot
+        {
+            self.dropped += 1;
+            return false;
+        }
+
+        shot.events += 1;
+        self.frame += 1;
+        true
+    }
+}
+
+#[derive(Event, Debug, Clone)]
 pub struct NodeOutputTrigger {
     pub data: NodeEventData,
+    pub owner: GraphOwner,
     pub node: egui_snarl::NodeId,
     pub output_index: usize,
 }
@@ -51,77 +343,126 @@ pub struct NodeOutputTrigger {
 #[derive(Event, Debug)]
 struct NodeTrigger {
     data: NodeEventData,
+    owner: GraphOwner,
     node: egui_snarl::NodeId,
 }
 
-#[derive(Event, Debug)]
+#[derive(Event, Debug, Clone)]
 pub enum WorldEvent {
     SpawnBullet {
-        loc: Option<Vec2>,
-        dir: Option<Vec2>,
+        data: NodeEventData,
+        owner: GraphOwner,
         id: egui_snarl::NodeId,
     },
     DealDmg {
-        target: Option<Entity>,
-        id: egui_snarl::NodeId,
-    },
-    Spread {
         data: NodeEventData,
+        owner: GraphOwner,
+        id: egui_snarl::NodeId,
     },
 }
 
 fn do_world_events(
     mut node_trigger: EventReader<NodeTrigger>,
     mut world: EventWriter<WorldEvent>,
-    snarl: Res<SnarlContainer>,
+    mut output_triggers: EventWriter<NodeOutputTrigger>,
+    runtime: Res<RuntimeGraph>,
+    graphs: Query<&NodeGraph>,
 ) {
     for event in node_trigger.read() {
-        let Some(node) = snarl.snarl.get_node(event.node) else {
+        let Some(node) =
+            graph_for(event.owner, &runtime, &graphs).and_then(|snarl| snarl.get_node(event.node))
+        else {
             continue;
         };
         match node {
             Node::SpawnBullet => {
                 world.send(WorldEvent::SpawnBullet {
-                    loc: event.data.loc,
-                    dir: event.data.dir,
+                    data: event.data.clone(),
+                    owner: event.owner,
                     id: event.node,
                 });
             }
             Node::DealDmg => {
                 world.send(WorldEvent::DealDmg {
-                    target: event.data.target,
+                    data: event.data.clone(),
+                    owner: event.owner,
                     id: event.node,
                 });
             }
-            Node::Spread => {
-                world.send(WorldEvent::Spread {
-                    data: event.data.clone(),
-                });
+            Node::Spread { count, angle } => {
+                for dir in spread_directions(event.data.dir, *count, *angle) {
+                    output_triggers.send(NodeOutputTrigger {
+                        data: NodeEventData {
+                            dir: Some(dir),
+                            ..event.data.clone()
+                        },
+                        owner: event.owner,
+                        node: event.node,
+                        output_index: 0,
+                    });
+                }
             }
-            Node::OnShoot => {}
-            _ => unimplemented!(),
+            Node::OnShoot | Node::Repeating | Node::Explosion | Node::Macro { .. } => {}
         }
     }
 }
+
 fn activate_nodes(
     mut output_triggers: EventReader<NodeOutputTrigger>,
     mut node_triggers: EventWriter<NodeTrigger>,
-    snarl: Res<SnarlContainer>,
+    runtime: Res<RuntimeGraph>,
+    graphs: Query<&NodeGraph>,
+    budget: Res<EventBudget>,
+    mut usage: ResMut<EventBudgetUsage>,
+    time: Res<Time>,
 ) {
+    usage.start_frame(time.elapsed_seconds());
+    let dropped_before = usage.dropped;
+
     for event in output_triggers.read() {
+        // The owner might have died since the event was sent
+        let Some(snarl) = graph_for(event.owner, &runtime, &graphs) else {
+            continue;
+        };
         let pin_id = egui_snarl::OutPinId {
             node: event.node,
             output: event.output_index,
         };
-        let pin = snarl.snarl.out_pin(pin_id);
+        let pin = snarl.out_pin(pin_id);
 
         for connected in pin.remotes {
+            let data = NodeEventData {
+                depth: event.data.depth + 1,
+                ..event.data.clone()
+            };
+            if !usage.try_spend(&budget, &data) {
+                continue;
+            }
             node_triggers.send(NodeTrigger {
-                data: event.data.clone(),
+                data,
+                owner: event.owner,
                 node: connected.node,
             });
         }
     }
+
+    // Only the first drop warns, the budget UI keeps count and clearing it there warns again
+    let dropped = usage.dropped - dropped_before;
+    if dropped > 0 && dropped_before == 0 {
+        bevy::log::warn!("Event budget exceeded, dropped {dropped} node events");
+    } else if dropped > 0 {
+        bevy::log::debug!("Event budget exceeded, dropped {dropped} node events");
+    }
+}
+
+/// What the player calls their graph, shown in the HUD
+#[derive(Resource, Debug)]
+pub struct GraphName(pub String);
+
+impl Default for GraphName {
+    fn default() -> Self {
+        Self(String::from("Pea Shooter"))
+    }
 }
 
 #[derive(Resource)]
@@ -138,7 +479,7 @@ impl Default for SnarlContainer {
         let bullet = snarl.insert_node(egui::Pos2::new(150.0, 0.0), Node::SpawnBullet);
         snarl.insert_node(egui::Pos2::new(150.0, 0.0), Node::SpawnBullet);
         snarl.insert_node(egui::Pos2::new(150.0, 0.0), Node::SpawnBullet);
-        snarl.insert_node(egui::Pos2::new(-150.0, 0.0), Node::Spread);
+        snarl.insert_node(egui::Pos2::new(-150.0, 0.0), Node::spread());
         let dmg = snarl.insert_node(egui::Pos2::new(150.0, 100.0), Node::DealDmg);
 
         snarl.connect(
@@ -169,52 +510,133 @@ impl Default for SnarlContainer {
     }
 }
 
-struct Viewer;
+struct Viewer<'a> {
+    library: &'a MacroLibrary,
+    inventory: &'a mut NodeInventory,
+    selection: &'a mut HashSet<egui_snarl::NodeId>,
+    /// Played once the editor is done drawing
+    sounds: Vec<Sound>,
+}
 
-impl egui_snarl::ui::SnarlViewer<Node> for Viewer {
+impl egui_snarl::ui::SnarlViewer<Node> for Viewer<'_> {
     fn title(&mut self, node: &Node) -> String {
         match node {
-            Node::OnShoot => String::from("On shoot"),
-            Node::SpawnBullet => String::from("Spawn Bullet"),
-            Node::Explosion => String::from("Spawn Explosion"),
-            Node::Repeating => String::from("Repeat"),
-            Node::DealDmg => String::from("Dmg"),
-            Node::Spread => String::from("Spread"),
+            Node::Macro { id, .. } => self.library.get(*id).map_or_else(
+                || String::from("Missing macro"),
+                |def| def.name().to_owned(),
+            ),
+            node => String::from(node.title()),
         }
     }
-    fn outputs(&mut self, node: &Node) -> usize {
-        match node {
-            Node::SpawnBullet => 2,
-            Node::Explosion | Node::Repeating | Node::DealDmg | Node::OnShoot | Node::Spread => 1,
+    fn show_header(
+        &mut self,
+        node: egui_snarl::NodeId,
+        _inputs: &[egui_snarl::InPin],
+        _outputs: &[egui_snarl::OutPin],
+        ui: &mut egui::Ui,
+        _scale: f32,
+        snarl: &mut egui_snarl::Snarl<Node>,
+    ) {
+        let Some(kind) = snarl.get_node(node) else {
+            return;
+        };
+        let title = self.title(kind);
+        let mut selected = self.selection.contains(&node);
+        if ui.checkbox(&mut selected, title).changed() {
+            if selected {
+                self.selection.insert(node);
+            } else {
+                self.selection.remove(&node);
+            }
         }
     }
-    fn inputs(&mut self, node: &Node) -> usize {
-        match node {
-            Node::OnShoot => 0,
-            Node::SpawnBullet
-            | Node::Explosion
-            | Node::Repeating
-            | Node::DealDmg
-            | Node::Spread => 1,
+    fn graph_menu(
+        &mut self,
+        pos: egui::Pos2,
+        ui: &mut egui::Ui,
+        _scale: f32,
+        snarl: &mut egui_snarl::Snarl<Node>,
+    ) {
+        ui.label("Nodes");
+        for node in inventory::PALETTE {
+            let count = self.inventory.count(&node);
+            let button = egui::Button::new(format!("{} ({count})", node.title()));
+            if ui.add_enabled(count > 0, button).clicked() {
+                if self.inventory.take(&[node]).is_ok() {
+                    snarl.insert_node(pos, node);
+                }
+                ui.close_menu();
+            }
+        }
+        ui.separator();
+        ui.label("Macros");
+        for (id, def) in self.library.iter() {
+            let contents = self.library.contents(id);
+            let button = ui.add_enabled(
+                self.inventory.can_take(&contents),
+                egui::Button::new(def.name()),
+            );
+            if button.clicked() {
+                if let (Some(node), Ok(())) =
+                    (self.library.node(id), self.inventory.take(&contents))
+                {
+                    snarl.insert_node(pos, node);
+                }
+                ui.close_menu();
+            }
         }
     }
+    fn node_menu(
+        &mut self,
+        node: egui_snarl::NodeId,
+        _inputs: &[egui_snarl::InPin],
+        _outputs: &[egui_snarl::OutPin],
+        ui: &mut egui::Ui,
+        _scale: f32,
+        snarl: &mut egui_snarl::Snarl<Node>,
+    ) {
+        let Some(kind) = snarl.get_node(node).copied() else {
+            return;
+        };
+        // The graph is useless without its trigger
+        let remove = ui.add_enabled(kind != Node::OnShoot, egui::Button::new("Remove"));
+        if remove.clicked() {
+            let returned = match kind {
+                Node::Macro { id, .. } => self.library.contents(id),
+                _ => vec![kind],
+            };
+            self.inventory.give_back(&returned);
+            snarl.remove_node(node);
+            self.selection.remove(&node);
+            ui.close_menu();
+        }
+    }
+    fn outputs(&mut self, node: &Node) -> usize {
+        node.outputs()
+    }
+    fn inputs(&mut self, node: &Node) -> usize {
+        node.inputs()
+    }
     fn show_input(
         &mut self,
         pin: &egui_snarl::InPin,
         ui: &mut egui::Ui,
-        scale: f32,
+        _scale: f32,
         snarl: &mut egui_snarl::Snarl<Node>,
     ) -> egui_snarl::ui::PinInfo {
-        if let Some(node) = snarl.get_node(pin.id.node) {
-            let label = match node {
-                Node::OnShoot => "",
-                Node::SpawnBullet => "Spawn",
-                Node::Explosion => "Spawn",
-                Node::Repeating => "Event",
-                Node::DealDmg => "Target",
-                Node::Spread => "",
-            };
-            ui.label(label);
+        match snarl.get_node(pin.id.node) {
+            Some(Node::Macro { id, .. }) => {
+                let label = self
+                    .library
+                    .get(*id)
+                    .map(|def| def.input_label(pin.id.input))
+                    .unwrap_or_default();
+                ui.label(label);
+            }
+            Some(node) => {
+                ui.label(node.input_label());
+            }
+            None => {}
         }
 
         egui_snarl::ui::PinInfo::triangle().with_fill(if pin.remotes.is_empty() {
@@ -227,19 +649,22 @@ impl egui_snarl::ui::SnarlViewer<Node> for Viewer {
         &mut self,
         pin: &egui_snarl::OutPin,
         ui: &mut egui::Ui,
-        scale: f32,
+        _scale: f32,
         snarl: &mut egui_snarl::Snarl<Node>,
     ) -> egui_snarl::ui::PinInfo {
-        if let Some(node) = snarl.get_node(pin.id.node) {
-            let label = match node {
-                Node::OnShoot => "Hit",
-                Node::SpawnBullet => ["Hit", "Despawned"][pin.id.output],
-                Node::Explosion => "Hit",
-                Node::Repeating => "Event",
-                Node::DealDmg => "Fatal",
-                Node::Spread => "",
-            };
-            ui.label(label);
+        match snarl.get_node(pin.id.node) {
+            Some(Node::Macro { id, .. }) => {
+                let label = self
+                    .library
+                    .get(*id)
+                    .map(|def| def.output_label(pin.id.output))
+                    .unwrap_or_default();
+                ui.label(label);
+            }
+            Some(node) => {
+                ui.label(node.output_label(pin.id.output));
+            }
+            None => {}
         }
 
         egui_snarl::ui::PinInfo::circle().with_fill(if pin.remotes.is_empty() {
@@ -250,37 +675,44 @@ impl egui_snarl::ui::SnarlViewer<Node> for Viewer {
     }
     fn output_color(
         &mut self,
-        pin: &egui_snarl::OutPin,
-        style: &egui::Style,
-        snarl: &mut egui_snarl::Snarl<Node>,
+        _pin: &egui_snarl::OutPin,
+        _style: &egui::Style,
+        _snarl: &mut egui_snarl::Snarl<Node>,
     ) -> egui::Color32 {
         egui::Color32::GREEN
     }
     fn input_color(
         &mut self,
-        pin: &egui_snarl::InPin,
-        style: &egui::Style,
-        snarl: &mut egui_snarl::Snarl<Node>,
+        _pin: &egui_snarl::InPin,
+        _style: &egui::Style,
+        _snarl: &mut egui_snarl::Snarl<Node>,
     ) -> egui::Color32 {
         egui::Color32::GREEN
     }
-    fn has_body(&mut self, node: &Node) -> bool {
+    fn has_body(&mut self, _node: &Node) -> bool {
         true
     }
     fn show_body(
         &mut self,
         node_id: egui_snarl::NodeId,
-        inputs: &[egui_snarl::InPin],
-        outputs: &[egui_snarl::OutPin],
+        _inputs: &[egui_snarl::InPin],
+        _outputs: &[egui_snarl::OutPin],
         ui: &mut egui::Ui,
-        scale: f32,
+        _scale: f32,
         snarl: &mut egui_snarl::Snarl<Node>,
     ) {
-        let Some(node) = snarl.get_node(node_id) else {
+        let Some(node) = snarl.get_node_mut(node_id) else {
             return;
         };
-        match node {
-            _ => {}
+        if let Node::Spread { count, angle } = node {
+            ui.vertical(|ui| {
+                ui.add(
+                    egui::DragValue::new(count)
+                        .clamp_range(1..=MAX_SPREAD)
+                        .suffix(" bullets"),
+                );
+                ui.drag_angle(angle);
+            });
         }
     }
     fn connect(
@@ -289,29 +721,11 @@ impl egui_snarl::ui::SnarlViewer<Node> for Viewer {
         to: &egui_snarl::InPin,
         snarl: &mut egui_snarl::Snarl<Node>,
     ) {
-        let from_node_id = from.id.node;
-        // We should be disalloving all loops so this shouldnt hang
-        let mut stack = vec![to.id.node];
-        while let Some(node_id) = stack.pop() {
-            if node_id == from_node_id {
-                // TODO: Play some sort of error sound
-                return;
-            }
-            let Some(node) = snarl.get_node(node_id) else {
-                bevy::log::warn!("Node not found");
-                continue;
-            };
-            let count = self.outputs(node);
-            for i in 0..count {
-                let pin = snarl.out_pin(egui_snarl::OutPinId {
-                    node: node_id,
-                    output: i,
-                });
-                for pin in pin.remotes {
-                    stack.push(pin.node);
-                }
-            }
+        if would_loop(snarl, from.id.node, to.id.node) {
+            self.sounds.push(Sound::Reject);
+            return;
         }
+        self.sounds.push(Sound::Connect);
 
         // snarl.drop_outputs(from.id);
         snarl.drop_inputs(to.id);
@@ -319,12 +733,345 @@ impl egui_snarl::ui::SnarlViewer<Node> for Viewer {
     }
 }
 
-fn node_editor(mut ctx: EguiContexts, mut snarl: ResMut<SnarlContainer>) {
+/// Checks if connecting `from` to `to` would create a loop in the graph
+fn would_loop(
+    snarl: &egui_snarl::Snarl<Node>,
+    from: egui_snarl::NodeId,
+    to: egui_snarl::NodeId,
+) -> bool {
+    let mut visited = HashSet::new();
+    let mut stack = vec![to];
+    while let Some(node_id) = stack.pop() {
+        if node_id == from {
+            return true;
+        }
+        // Also keeps this from hanging if a loop ever does sneak into the graph
+        if !visited.insert(node_id) {
+            continue;
+        }
+        let Some(node) = snarl.get_node(node_id) else {
+            bevy::log::warn!("Node not found");
+            continue;
+        };
+        for i in 0..node.outputs() {
+            let pin = snarl.out_pin(egui_snarl::OutPinId {
+                node: node_id,
+                output: i,
+            });
+            for pin in pin.remotes {
+                stack.push(pin.node);
+            }
+        }
+    }
+    false
+}
+
+#[derive(Default)]
+struct TextEditor {
+    text: String,
+    error: Option<String>,
+}
+
+#[derive(Default)]
+struct BuildCodeEditor {
+    code: String,
+    error: Option<String>,
+}
+
+#[derive(Default)]
+struct MacroEditor {
+    name: String,
+    selection: HashSet<egui_snarl::NodeId>,
+    error: Option<String>,
+}
+
+#[derive(Default)]
+struct EditorState {
+    text: TextEditor,
+    build_code: BuildCodeEditor,
+    macros: MacroEditor,
+}
+
+/// The settings shown above the graph
+#[derive(SystemParam)]
+struct EditorSettings<'w> {
+    name: ResMut<'w, GraphName>,
+    editor_time: ResMut<'w, EditorTime>,
+    budget: ResMut<'w, EventBudget>,
+    usage: ResMut<'w, EventBudgetUsage>,
+}
+
+fn node_editor(
+    mut ctx: EguiContexts,
+    mut snarl: ResMut<SnarlContainer>,
+    mut library: ResMut<MacroLibrary>,
+    mut inventory: ResMut<NodeInventory>,
+    mut settings: EditorSettings,
+    mut sounds: EventWriter<PlaySound>,
+    mut state: Local<EditorState>,
+) {
+    let ctx = ctx.ctx_mut();
+    let mut frame = egui::Frame::window(&ctx.style());
+    // See through to the game while it keeps running behind the editor
+    if settings.editor_time.live {
+        frame = frame.fill(frame.fill.gamma_multiply(0.6));
+    }
+
     egui::Window::new("Node Editor")
         .default_size((1500.0, 900.0))
-        .show(ctx.ctx_mut(), |ui| {
+        .frame(frame)
+        .show(ctx, |ui| {
+            name_ui(ui, &mut settings.name);
+            time_ui(ui, &mut settings.editor_time);
+            budget_ui(ui, &mut settings.budget, &mut settings.usage);
+            build_code_ui(ui, &mut snarl, &library, &mut inventory, &mut state);
+            ui.collapsing("Text", |ui| {
+                text_ui(ui, &mut snarl, &library, &mut inventory, &mut state);
+            });
+            macro_ui(ui, &mut snarl, &mut library, &mut state.macros);
+
             let style = egui_snarl::ui::SnarlStyle::new();
+            let mut viewer = Viewer {
+                library: &library,
+                inventory: &mut inventory,
+                selection: &mut state.macros.selection,
+                sounds: Vec::new(),
+            };
+            snarl.snarl.show(&mut viewer, &style, "node_editor", ui);
+            sounds.send_batch(viewer.sounds.into_iter().map(PlaySound));
+        });
+}
+
+fn name_ui(ui: &mut egui::Ui, name: &mut ResMut<GraphName>) {
+    ui.horizontal(|ui| {
+        ui.label("Name");
+        let mut edited = name.0.clone();
+        if ui.text_edit_singleline(&mut edited).changed() {
+            name.0 = edited;
+        }
+    });
+}
 
-            snarl.snarl.show(&mut Viewer, &style, "node_editor", ui);
+fn time_ui(ui: &mut egui::Ui, editor_time: &mut ResMut<EditorTime>) {
+    // Only touch the resource on a change, the time speed updates when it changes
+    let mut edited = EditorTime {
+        live: editor_time.live,
+        speed: editor_time.speed,
+    };
+    let changed = ui
+        .horizontal(|ui| {
+            let live = ui
+                .checkbox(&mut edited.live, "Keep the game running while editing")
+                .changed();
+            let speed = ui
+                .add_enabled(
+                    edited.live,
+                    egui::Slider::new(&mut edited.speed, 0.05..=1.0).text("speed"),
+                )
+                .changed();
+            live || speed
+        })
+        .inner;
+    if changed {
+        **editor_time = edited;
+    }
+}
+
+fn budget_ui(ui: &mut egui::Ui, budget: &mut EventBudget, usage: &mut EventBudgetUsage) {
+    ui.horizontal(|ui| {
+        ui.label("Events per frame");
+        ui.add(egui::DragValue::new(&mut budget.per_frame).clamp_range(1..=10_000));
+        ui.label("Events per shot");
+        ui.add(egui::DragValue::new(&mut budget.per_shot).clamp_range(1..=100_000));
+        ui.label("Max depth");
+        ui.add(egui::DragValue::new(&mut budget.max_depth).clamp_range(1..=256));
+    });
+    if usage.dropped > 0 {
+        ui.horizontal(|ui| {
+            ui.colored_label(
+                egui::Color32::YELLOW,
+                format!(
+                    "⚠ {} events were dropped by the event budget, your graph might be exploding",
+                    usage.dropped
+                ),
+            );
+            if ui.button("Clear").clicked() {
+                usage.dropped = 0;
+            }
         });
+    }
+}
+
+fn build_code_ui(
+    ui: &mut egui::Ui,
+    snarl: &mut SnarlContainer,
+    library: &MacroLibrary,
+    inventory: &mut NodeInventory,
+    state: &mut EditorState,
+) {
+    let editor = &mut state.build_code;
+    ui.horizontal(|ui| {
+        ui.label("Build code");
+        ui.text_edit_singleline(&mut editor.code);
+        if ui.button("Copy build code").clicked() {
+            editor.code = build_code::encode(&library.expand_container(snarl));
+            editor.error = None;
+            ui.output_mut(|output| output.copied_text.clone_from(&editor.code));
+        }
+        if ui.button("Paste build code").clicked() {
+            let decoded = build_code::decode(&editor.code).map_err(|err| err.to_string());
+            match decoded.and_then(|decoded| replace_graph(snarl, decoded, library, inventory)) {
+                Ok(()) => {
+                    editor.error = None;
+                    state.macros.selection.clear();
+                }
+                Err(err) => editor.error = Some(err),
+            }
+        }
+        if let Some(error) = &editor.error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+    });
 }
+
+fn text_ui(
+    ui: &mut egui::Ui,
+    snarl: &mut SnarlContainer,
+    library: &MacroLibrary,
+    inventory: &mut NodeInventory,
+    state: &mut EditorState,
+) {
+    let editor = &mut state.text;
+    ui.horizontal(|ui| {
+        if ui.button("Export").clicked() {
+            editor.text = dsl::export(&library.expand_container(snarl));
+            editor.error = None;
+        }
+        if ui.button("Import").clicked() {
+            let parsed = dsl::parse(&editor.text).map_err(|err| err.to_string());
+            match parsed.and_then(|parsed| replace_graph(snarl, parsed, library, inventory)) {
+                Ok(()) => {
+                    editor.error = None;
+                    state.macros.selection.clear();
+                }
+                Err(err) => editor.error = Some(err),
+            }
+        }
+    });
+    if let Some(error) = &editor.error {
+        ui.colored_label(egui::Color32::RED, error);
+    }
+    ui.add(
+        egui::TextEdit::multiline(&mut editor.text)
+            .code_editor()
+            .desired_width(f32::INFINITY),
+    );
+}
+
+/// Swaps in a whole new graph, as long as the inventory has the nodes for it
+fn replace_graph(
+    snarl: &mut SnarlContainer,
+    new: SnarlContainer,
+    library: &MacroLibrary,
+    inventory: &mut NodeInventory,
+) -> Result<(), String> {
+    inventory.swap(
+        &library.graph_contents(&snarl.snarl),
+        &library.graph_contents(&new.snarl),
+    )?;
+    *snarl = new;
+    Ok(())
+}
+
+fn macro_ui(
+    ui: &mut egui::Ui,
+    snarl: &mut SnarlContainer,
+    library: &mut MacroLibrary,
+    editor: &mut MacroEditor,
+) {
+    ui.horizontal(|ui| {
+        ui.label("Macro name");
+        ui.text_edit_singleline(&mut editor.name);
+        let collapse = ui.add_enabled(
+            !editor.selection.is_empty() && !editor.name.is_empty(),
+            egui::Button::new(format!(
+                "Collapse {} selected into macro",
+                editor.selection.len()
+            )),
+        );
+        if collapse.clicked() {
+            let name = std::mem::take(&mut editor.name);
+            match library.collapse(name, &mut snarl.snarl, &editor.selection) {
+                Ok(_) => {
+                    editor.selection.clear();
+                    editor.error = None;
+                }
+                Err(err) => editor.error = Some(err),
+            }
+        }
+        if let Some(error) = &editor.error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+    });
+}
+
+#[derive(Default)]
+struct PatternEditor {
+    selected: Option<&'static str>,
+    text: String,
+    error: Option<String>,
+}
+
+/// Lets enemy attack patterns be written and tried out without restarting the game
+fn pattern_editor(
+    mut ctx: EguiContexts,
+    mut patterns: ResMut<EnemyPatterns>,
+    snarl: Res<SnarlContainer>,
+    library: Res<MacroLibrary>,
+    mut editor: Local<PatternEditor>,
+) {
+    egui::Window::new("Enemy Patterns")
+        .anchor(egui::Align2::LEFT_BOTTOM, egui::Vec2::new(8.0, -8.0))
+        .default_open(false)
+        .show(ctx.ctx_mut(), |ui| {
+            ui.horizontal_wrapped(|ui| {
+                for name in patterns.names() {
+                    if ui
+                        .selectable_label(editor.selected == Some(name), name)
+                        .clicked()
+                    {
+                        editor.selected = Some(name);
+                        patterns
+                            .text(name)
+                            .unwrap_or_default()
+                            .clone_into(&mut editor.text);
+                        editor.error = None;
+                    }
+                }
+            });
+            let Some(name) = editor.selected else {
+                ui.label("Pick a pattern to edit");
+                return;
+            };
+            ui.horizontal(|ui| {
+                if ui.button("Use my graph").clicked() {
+                    editor.text = dsl::export(&library.expand_container(&snarl));
+                    editor.error = None;
+                }
+                if ui.button("Save").clicked() {
+                    editor.error = patterns.set(name, editor.text.clone()).err();
+                }
+            });
+            if let Some(error) = &editor.error {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+            ui.add(
+                egui::TextEdit::multiline(&mut editor.text)
+                    .code_editor()
+                    .desired_width(400.0),
+            );
+        });
+}
+
+#[cfg(test)]
+mod tests;
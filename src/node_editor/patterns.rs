@@ -0,0 +1,59 @@
+//! Enemy attack patterns by name, kept as text so they can be changed from the node editor while
+//! playing. Whatever registers a pattern gives its default, enemies spawned after an edit use the
+//! new one.
+
+use super::NodeGraph;
+use crate::prelude::*;
+
+struct Pattern {
+    name: &'static str,
+    text: String,
+}
+
+#[derive(Resource, Default)]
+pub struct EnemyPatterns {
+    patterns: Vec<Pattern>,
+}
+
+impl EnemyPatterns {
+    /// Adds a pattern, keeping the current text if one with the name is already there
+    pub fn register(&mut self, name: &'static str, text: &str) {
+        if self.text(name).is_none() {
+            self.patterns.push(Pattern {
+                name,
+                text: text.to_owned(),
+            });
+        }
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.patterns.iter().map(|pattern| pattern.name)
+    }
+
+    pub fn text(&self, name: &str) -> Option<&str> {
+        self.patterns
+            .iter()
+            .find(|pattern| pattern.name == name)
+            .map(|pattern| pattern.text.as_str())
+    }
+
+    /// Replaces a pattern, as long as the new text is a valid graph
+    pub fn set(&mut self, name: &str, text: String) -> Result<(), String> {
+        NodeGraph::from_dsl(&text).map_err(|err| err.to_string())?;
+        let pattern = self
+            .patterns
+            .iter_mut()
+            .find(|pattern| pattern.name == name)
+            .ok_or_else(|| format!("there is no pattern called {name}"))?;
+        pattern.text = text;
+        Ok(())
+    }
+
+    /// A fresh graph for the pattern, `None` if there is no such pattern or it is broken
+    pub fn graph(&self, name: &str) -> Option<NodeGraph> {
+        let text = self.text(name)?;
+        NodeGraph::from_dsl(text)
+            .map_err(|err| bevy::log::warn!("The {name} pattern is broken: {err}"))
+            .ok()
+    }
+}
@@ -0,0 +1,803 @@
+#![allow(clippy::panic)]
+
+use egui_snarl::{InPinId, NodeId, OutPinId, Snarl};
+
+use super::*;
+
+#[derive(Resource, Default)]
+struct RecordedEvents(Vec<WorldEvent>);
+
+fn record_world_events(mut events: EventReader<WorldEvent>, mut recorded: ResMut<RecordedEvents>) {
+    recorded.0.extend(events.read().cloned());
+}
+
+/// A headless app running only the node runtime, used to drive graphs without playing
+struct Harness {
+    app: App,
+}
+
+impl Harness {
+    fn new(snarl: Snarl<Node>, shoot_trigger: NodeId) -> Self {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .insert_state(PlayingState::ShootyTime)
+            .add_plugins(NodeRuntimePlugin)
+            .insert_resource(SnarlContainer {
+                snarl,
+                shoot_trigger,
+            })
+            .init_resource::<RecordedEvents>()
+            .add_systems(Update, record_world_events.after(NodeRuntimeSet));
+        Self { app }
+    }
+
+    fn with_library(mut self, library: MacroLibrary) -> Self {
+        self.app.insert_resource(library);
+        self
+    }
+
+    fn from_text(text: &str) -> Self {
+        let container = dsl::parse(text).unwrap_or_else(|err| panic!("{err}"));
+        Self::new(container.snarl, container.shoot_trigger)
+    }
+
+    fn shoot(&mut self) {
+        let shot = self.app.world.resource_mut::<EventBudgetUsage>().new_shot();
+        let node = self.app.world.resource::<SnarlContainer>().shoot_trigger;
+        self.trigger(
+            node,
+            0,
+            NodeEventData {
+                loc: Some(Vec2::ZERO),
+                dir: Some(Vec2::X),
+                shot,
+                ..default()
+            },
+        );
+    }
+
+    fn trigger(&mut self, node: NodeId, output_index: usize, data: NodeEventData) {
+        self.trigger_as(GraphOwner::Player, node, output_index, data);
+    }
+
+    fn trigger_as(
+        &mut self,
+        owner: GraphOwner,
+        node: NodeId,
+        output_index: usize,
+        data: NodeEventData,
+    ) {
+        self.app.world.send_event(NodeOutputTrigger {
+            data,
+            owner,
+            node,
+            output_index,
+        });
+    }
+
+    fn step(&mut self, frames: usize) {
+        for _ in 0..frames {
+            self.app.update();
+        }
+    }
+
+    fn take_events(&mut self) -> Vec<WorldEvent> {
+        std::mem::take(&mut self.app.world.resource_mut::<RecordedEvents>().0)
+    }
+
+    fn budget(&mut self) -> Mut<'_, EventBudget> {
+        self.app.world.resource_mut::<EventBudget>()
+    }
+}
+
+fn wire(snarl: &mut Snarl<Node>, from: NodeId, output: usize, to: NodeId) {
+    snarl.connect(
+        OutPinId { node: from, output },
+        InPinId { node: to, input: 0 },
+    );
+}
+
+/// `OnShoot` wired straight into a single node of the given kind
+fn shoot_into(node: Node) -> (Snarl<Node>, NodeId, NodeId) {
+    let mut snarl = Snarl::new();
+    let shoot = snarl.insert_node(egui::Pos2::ZERO, Node::OnShoot);
+    let target = snarl.insert_node(egui::Pos2::ZERO, node);
+    wire(&mut snarl, shoot, 0, target);
+    (snarl, shoot, target)
+}
+
+#[test]
+fn on_shoot_without_wires_does_nothing() {
+    let mut snarl = Snarl::new();
+    let shoot = snarl.insert_node(egui::Pos2::ZERO, Node::OnShoot);
+    let mut harness = Harness::new(snarl, shoot);
+
+    harness.shoot();
+    harness.step(3);
+
+    assert!(harness.take_events().is_empty());
+}
+
+#[test]
+fn spawn_bullet_emits_bullet_at_shot() {
+    let (snarl, shoot, bullet) = shoot_into(Node::SpawnBullet);
+    let mut harness = Harness::new(snarl, shoot);
+
+    harness.shoot();
+    harness.step(1);
+
+    let events = harness.take_events();
+    assert!(
+        matches!(
+            events.as_slice(),
+            [WorldEvent::SpawnBullet { data, id, .. }] if *id == bullet
+                && data.loc == Some(Vec2::ZERO)
+                && data.dir == Some(Vec2::X)
+                && data.depth == 1
+        ),
+        "{events:?}"
+    );
+}
+
+#[test]
+fn deal_dmg_emits_damage_for_target() {
+    let (snarl, shoot, dmg) = shoot_into(Node::DealDmg);
+    let mut harness = Harness::new(snarl, shoot);
+    let target = harness.app.world.spawn_empty().id();
+
+    harness.trigger(
+        shoot,
+        0,
+        NodeEventData {
+            target: Some(target),
+            ..default()
+        },
+    );
+    harness.step(1);
+
+    let events = harness.take_events();
+    assert!(
+        matches!(
+            events.as_slice(),
+            [WorldEvent::DealDmg { data, id, .. }] if data.target == Some(target) && *id == dmg
+        ),
+        "{events:?}"
+    );
+}
+
+#[test]
+fn spread_fans_out_around_the_direction() {
+    let mut harness = Harness::from_text("on_shoot -> spread(3, 90deg) -> spawn_bullet");
+
+    harness.shoot();
+    harness.step(2);
+
+    let dirs: Vec<_> = harness
+        .take_events()
+        .into_iter()
+        .filter_map(|event| match event {
+            WorldEvent::SpawnBullet { data, .. } => data.dir,
+            WorldEvent::DealDmg { .. } => None,
+        })
+        .collect();
+    let expected = [
+        Vec2::from_angle(-std::f32::consts::FRAC_PI_4),
+        Vec2::X,
+        Vec2::from_angle(std::f32::consts::FRAC_PI_4),
+    ];
+    assert_eq!(dirs.len(), expected.len(), "{dirs:?}");
+    for (dir, expected) in dirs.iter().zip(expected) {
+        assert!(dir.abs_diff_eq(expected, 1e-5), "{dirs:?}");
+    }
+}
+
+#[test]
+fn spread_without_direction_goes_all_the_way_around() {
+    let dirs = spread_directions(None, 4, 0.0);
+    let expected = [Vec2::X, Vec2::Y, Vec2::NEG_X, Vec2::NEG_Y];
+    for (dir, expected) in dirs.iter().zip(expected) {
+        assert!(dir.abs_diff_eq(expected, 1e-5), "{dirs:?}");
+    }
+}
+
+#[test]
+fn unfinished_nodes_are_inert() {
+    for node in [Node::Repeating, Node::Explosion] {
+        let (snarl, shoot, _) = shoot_into(node);
+        let mut harness = Harness::new(snarl, shoot);
+
+        harness.shoot();
+        harness.step(3);
+
+        assert!(harness.take_events().is_empty(), "{node:?}");
+    }
+}
+
+#[test]
+fn bullet_outputs_continue_the_chain() {
+    let mut snarl = Snarl::new();
+    let shoot = snarl.insert_node(egui::Pos2::ZERO, Node::OnShoot);
+    let bullet = snarl.insert_node(egui::Pos2::ZERO, Node::SpawnBullet);
+    let dmg = snarl.insert_node(egui::Pos2::ZERO, Node::DealDmg);
+    let despawn = snarl.insert_node(egui::Pos2::ZERO, Node::SpawnBullet);
+    wire(&mut snarl, shoot, 0, bullet);
+    wire(&mut snarl, bullet, 0, dmg);
+    wire(&mut snarl, bullet, 1, despawn);
+    let mut harness = Harness::new(snarl, shoot);
+
+    // Stand in for gameplay reporting a hit and a despawn
+    harness.trigger(bullet, 0, NodeEventData::default());
+    harness.trigger(
+        bullet,
+        1,
+        NodeEventData {
+            loc: Some(Vec2::ONE),
+            ..default()
+        },
+    );
+    harness.step(1);
+
+    let events = harness.take_events();
+    assert_eq!(events.len(), 2, "{events:?}");
+    assert!(events
+        .iter()
+        .any(|event| matches!(event, WorldEvent::DealDmg { id, .. } if *id == dmg)));
+    assert!(events.iter().any(|event| matches!(
+        event,
+        WorldEvent::SpawnBullet { data, id, .. } if *id == despawn && data.loc == Some(Vec2::ONE)
+    )));
+}
+
+#[test]
+fn default_graph_shoots_a_bullet() {
+    let SnarlContainer {
+        snarl,
+        shoot_trigger,
+    } = SnarlContainer::default();
+    let mut harness = Harness::new(snarl, shoot_trigger);
+
+    harness.shoot();
+    harness.step(1);
+
+    let events = harness.take_events();
+    assert!(
+        matches!(events.as_slice(), [WorldEvent::SpawnBullet { .. }]),
+        "{events:?}"
+    );
+}
+
+#[test]
+fn frame_budget_drops_excess_events() {
+    let mut snarl = Snarl::new();
+    let shoot = snarl.insert_node(egui::Pos2::ZERO, Node::OnShoot);
+    for _ in 0..10 {
+        let bullet = snarl.insert_node(egui::Pos2::ZERO, Node::SpawnBullet);
+        wire(&mut snarl, shoot, 0, bullet);
+    }
+    let mut harness = Harness::new(snarl, shoot);
+    harness.budget().per_frame = 4;
+
+    harness.shoot();
+    harness.step(1);
+
+    assert_eq!(harness.take_events().len(), 4);
+    assert_eq!(harness.app.world.resource::<EventBudgetUsage>().dropped, 6);
+
+    // The frame budget resets, so the next shot gets through again
+    harness.shoot();
+    harness.step(1);
+    assert_eq!(harness.take_events().len(), 4);
+}
+
+#[test]
+fn shot_budget_is_shared_across_frames() {
+    let (snarl, shoot, bullet) = shoot_into(Node::SpawnBullet);
+    let mut harness = Harness::new(snarl, shoot);
+    harness.budget().per_shot = 3;
+
+    for _ in 0..5 {
+        harness.trigger(
+            shoot,
+            0,
+            NodeEventData {
+                loc: Some(Vec2::ZERO),
+                shot: 7,
+                ..default()
+            },
+        );
+        harness.step(1);
+    }
+    assert_eq!(harness.take_events().len(), 3);
+
+    // A different shot has its own budget
+    harness.trigger(
+        shoot,
+        0,
+        NodeEventData {
+            shot: 8,
+            ..default()
+        },
+    );
+    harness.step(1);
+    assert!(matches!(
+        harness.take_events().as_slice(),
+        [WorldEvent::SpawnBullet { id, .. }] if *id == bullet
+    ));
+}
+
+#[test]
+fn max_depth_stops_deep_chains() {
+    let (snarl, shoot, _) = shoot_into(Node::SpawnBullet);
+    let mut harness = Harness::new(snarl, shoot);
+    harness.budget().max_depth = 5;
+
+    harness.trigger(
+        shoot,
+        0,
+        NodeEventData {
+            depth: 4,
+            ..default()
+        },
+    );
+    harness.step(1);
+    assert_eq!(harness.take_events().len(), 1);
+
+    harness.trigger(
+        shoot,
+        0,
+        NodeEventData {
+            depth: 5,
+            ..default()
+        },
+    );
+    harness.step(1);
+    assert!(harness.take_events().is_empty());
+}
+
+#[test]
+fn dsl_builds_the_described_graph() {
+    let mut harness = Harness::from_text(
+        "# bullets that hurt on hit and fizzle out\n\
+         on_shoot -> bullet: spawn_bullet.hit -> deal_dmg\n\
+         bullet.despawned -> explosion; bullet.despawned -> repeat",
+    );
+
+    harness.shoot();
+    harness.step(1);
+    assert!(matches!(
+        harness.take_events().as_slice(),
+        [WorldEvent::SpawnBullet { .. }]
+    ));
+
+    let container = harness.app.world.resource::<SnarlContainer>();
+    let snarl = &container.snarl;
+    let bullet = snarl.out_pin(OutPinId {
+        node: snarl
+            .in_pin(InPinId {
+                node: NodeId(2),
+                input: 0,
+            })
+            .remotes[0]
+            .node,
+        output: 1,
+    });
+    assert_eq!(bullet.remotes.len(), 2);
+}
+
+#[test]
+fn dsl_reports_errors_with_lines() {
+    let cases = [
+        ("spawn_bullet", None),
+        ("on_shoot -> shoot_laser", Some(1)),
+        ("on_shoot\non_shoot -> on_shoot", Some(2)),
+        ("on_shoot -> a: deal_dmg -> b: deal_dmg\nb -> a", Some(2)),
+        ("on_shoot -> spread(2.5)", Some(1)),
+        ("on_shoot -> spread(256)", Some(1)),
+        ("on_shoot -> spawn_bullet.boom -> deal_dmg", Some(1)),
+        ("on_shoot -> spawn_bullet.hit", Some(1)),
+        ("on_shoot -> spawn_bullet: deal_dmg", Some(1)),
+    ];
+    for (text, line) in cases {
+        let err = dsl::parse(text).err();
+        assert_eq!(err.map(|err| err.line), Some(line), "{text}");
+    }
+}
+
+#[test]
+fn dsl_export_round_trips() {
+    let text = "on_shoot -> spread(5, 30deg) -> spawn_bullet_1: spawn_bullet.hit -> deal_dmg\n\
+                spawn_bullet_1.despawned -> explosion\n\
+                spawn_bullet\n";
+    let container = dsl::parse(text).unwrap_or_else(|err| panic!("{err}"));
+    let exported = dsl::export(&container);
+    assert_eq!(exported, text);
+
+    let default = SnarlContainer::default();
+    let reparsed = dsl::parse(&dsl::export(&default)).unwrap_or_else(|err| panic!("{err}"));
+    assert_eq!(dsl::export(&reparsed), dsl::export(&default));
+    assert_eq!(
+        reparsed.snarl.nodes().count(),
+        default.snarl.nodes().count()
+    );
+
+    // Angles dragged in the editor are rarely whole degrees
+    for angle in [0.123_456_7_f32, 1.0, 33.3_f32.to_radians()] {
+        let mut snarl = Snarl::new();
+        let shoot_trigger = snarl.insert_node(egui::Pos2::ZERO, Node::OnShoot);
+        snarl.insert_node(egui::Pos2::ZERO, Node::Spread { count: 3, angle });
+        let container = SnarlContainer {
+            snarl,
+            shoot_trigger,
+        };
+        let reparsed = dsl::parse(&dsl::export(&container)).unwrap_or_else(|err| panic!("{err}"));
+        let angles: Vec<_> = reparsed
+            .snarl
+            .nodes()
+            .filter_map(|node| match node {
+                Node::Spread { angle, .. } => Some(angle.to_bits()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(angles, [angle.to_bits()], "{}", dsl::export(&container));
+    }
+}
+
+#[test]
+fn build_codes_round_trip() {
+    let container = dsl::parse(
+        "on_shoot -> spread(255, 30deg) -> bullet: spawn_bullet.hit -> deal_dmg\n\
+         bullet.despawned -> explosion\n\
+         repeat",
+    )
+    .unwrap_or_else(|err| panic!("{err}"));
+
+    let code = build_code::encode(&container);
+    let decoded = build_code::decode(&code).unwrap_or_else(|err| panic!("{err}"));
+
+    assert_eq!(dsl::export(&decoded), dsl::export(&container));
+    let positions = |container: &SnarlContainer| -> Vec<_> {
+        container.snarl.nodes_pos().map(|(pos, _)| pos).collect()
+    };
+    assert_eq!(positions(&decoded), positions(&container));
+}
+
+#[test]
+fn build_codes_reject_damaged_input() {
+    let code = build_code::encode(&SnarlContainer::default());
+
+    assert_eq!(
+        build_code::decode("not a code!").err(),
+        Some(build_code::BuildCodeError::NotBase64)
+    );
+    assert!(build_code::decode(&code[..code.len() / 2]).is_err());
+
+    let mut typo = code.into_bytes();
+    typo[3] = if typo[3] == b'A' { b'B' } else { b'A' };
+    let typo = String::from_utf8(typo).unwrap_or_default();
+    assert_eq!(
+        build_code::decode(&typo).err(),
+        Some(build_code::BuildCodeError::BadChecksum)
+    );
+}
+
+#[test]
+fn build_codes_reject_invalid_graphs() {
+    use base64::Engine;
+
+    let encode = |mut bytes: Vec<u8>| {
+        let sum = bytes.iter().fold(0x811c_9dc5_u32, |hash, byte| {
+            (hash ^ u32::from(*byte)).wrapping_mul(0x0100_0193)
+        });
+        bytes.extend(sum.to_le_bytes());
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+    };
+    #[rustfmt::skip]
+    let looping = vec![
+        1, // version
+        3, 0, // nodes
+        0, 0, 0, 0, 0, // on shoot
+        2, 0, 0, 0, 0, // deal dmg
+        2, 0, 0, 0, 0, // deal dmg
+        0, 0, // shoot trigger
+        2, 0, // wires
+        1, 0, 0, 2, 0, // dmg -> dmg
+        2, 0, 0, 1, 0, // and back again
+    ];
+    assert_eq!(
+        build_code::decode(&encode(looping)).err(),
+        Some(build_code::BuildCodeError::InvalidGraph(String::from(
+            "the graph loops"
+        )))
+    );
+    #[rustfmt::skip]
+    let wrong_trigger = vec![
+        1, // version
+        1, 0, // nodes
+        2, 0, 0, 0, 0, // deal dmg
+        0, 0, // shoot trigger
+        0, 0, // wires
+    ];
+    assert!(matches!(
+        build_code::decode(&encode(wrong_trigger)),
+        Err(build_code::BuildCodeError::InvalidGraph(_))
+    ));
+    #[rustfmt::skip]
+    let two_triggers = vec![
+        1, // version
+        2, 0, // nodes
+        0, 0, 0, 0, 0, // on shoot
+        0, 0, 0, 0, 0, // another on shoot
+        0, 0, // shoot trigger
+        0, 0, // wires
+    ];
+    assert_eq!(
+        build_code::decode(&encode(two_triggers)).err(),
+        Some(build_code::BuildCodeError::InvalidGraph(String::from(
+            "more than one on shoot node"
+        )))
+    );
+}
+
+fn bullet_count(events: &[WorldEvent]) -> usize {
+    events
+        .iter()
+        .filter(|event| matches!(event, WorldEvent::SpawnBullet { .. }))
+        .count()
+}
+
+#[test]
+fn macros_expose_the_pins_leaving_the_selection() {
+    let mut container = dsl::parse("on_shoot -> spread(3, 90deg) -> spawn_bullet.hit -> deal_dmg")
+        .unwrap_or_else(|err| panic!("{err}"));
+    let mut library = MacroLibrary::default();
+    let selection = [NodeId(1), NodeId(2), NodeId(3)].into_iter().collect();
+
+    let node = library
+        .collapse(String::from("Shotgun"), &mut container.snarl, &selection)
+        .unwrap_or_else(|err| panic!("{err}"));
+
+    assert_eq!(container.snarl.nodes().count(), 2);
+    assert_eq!(
+        container.snarl.get_node(node),
+        Some(&Node::Macro {
+            id: 0,
+            inputs: 1,
+            outputs: 2,
+        })
+    );
+    assert_eq!(
+        container.snarl.in_pin(InPinId { node, input: 0 }).remotes,
+        vec![OutPinId {
+            node: container.shoot_trigger,
+            output: 0,
+        }]
+    );
+    let Some(def) = library.get(0) else {
+        panic!("macro was not added to the library");
+    };
+    assert_eq!(def.input_label(0), "Spread");
+    assert_eq!(def.output_label(0), "Spawn Bullet: Despawned");
+    assert_eq!(def.output_label(1), "Dmg: Fatal");
+}
+
+#[test]
+fn macros_run_like_the_nodes_they_replace() {
+    let mut container = dsl::parse("on_shoot -> spread(3, 90deg) -> spawn_bullet.hit -> deal_dmg")
+        .unwrap_or_else(|err| panic!("{err}"));
+    let mut library = MacroLibrary::default();
+    let selection = [NodeId(1), NodeId(2)].into_iter().collect();
+    library
+        .collapse(String::from("Shotgun"), &mut container.snarl, &selection)
+        .unwrap_or_else(|err| panic!("{err}"));
+
+    let mut harness = Harness::new(container.snarl, container.shoot_trigger).with_library(library);
+    harness.shoot();
+    harness.step(2);
+
+    assert_eq!(bullet_count(&harness.take_events()), 3);
+}
+
+#[test]
+fn macros_can_be_nested_and_reused() {
+    let mut container = dsl::parse("on_shoot -> spread(2, 10deg) -> spawn_bullet")
+        .unwrap_or_else(|err| panic!("{err}"));
+    let mut library = MacroLibrary::default();
+    let double = library
+        .collapse(
+            String::from("Double"),
+            &mut container.snarl,
+            &[NodeId(1), NodeId(2)].into_iter().collect(),
+        )
+        .unwrap_or_else(|err| panic!("{err}"));
+    let nested = library
+        .collapse(
+            String::from("Still double"),
+            &mut container.snarl,
+            &[double].into_iter().collect(),
+        )
+        .unwrap_or_else(|err| panic!("{err}"));
+
+    assert_eq!(library.node(1), container.snarl.get_node(nested).copied());
+
+    // A second copy of the nested macro, also firing on shoot
+    let Some(copy) = library.node(1) else {
+        panic!("nested macro missing");
+    };
+    let copy = container.snarl.insert_node(egui::Pos2::ZERO, copy);
+    wire(&mut container.snarl, container.shoot_trigger, 0, copy);
+
+    let expanded = library.expand(&container.snarl);
+    assert!(!expanded
+        .nodes()
+        .any(|node| matches!(node, Node::Macro { .. })));
+
+    let mut harness = Harness::new(container.snarl, container.shoot_trigger).with_library(library);
+    harness.shoot();
+    harness.step(2);
+    assert_eq!(bullet_count(&harness.take_events()), 4);
+}
+
+#[test]
+fn macros_can_not_take_the_shoot_trigger() {
+    let mut container = SnarlContainer::default();
+    let mut library = MacroLibrary::default();
+    let selection = [container.shoot_trigger].into_iter().collect();
+
+    assert!(library
+        .collapse(String::from("Oops"), &mut container.snarl, &selection)
+        .is_err());
+    assert!(library.get(0).is_none());
+}
+
+#[test]
+fn macros_can_not_wrap_around_outside_nodes() {
+    let mut container = dsl::parse("on_shoot -> a: spread -> b: spread -> c: spread")
+        .unwrap_or_else(|err| panic!("{err}"));
+    let mut library = MacroLibrary::default();
+    let selection = [NodeId(1), NodeId(3)].into_iter().collect();
+
+    assert!(library
+        .collapse(String::from("Loop"), &mut container.snarl, &selection)
+        .is_err());
+    assert_eq!(container.snarl.nodes().count(), 4);
+}
+
+#[test]
+fn loop_checks_finish_on_looped_graphs() {
+    let mut container =
+        dsl::parse("on_shoot -> a: spread -> b: spread").unwrap_or_else(|err| panic!("{err}"));
+    // Wired behind the editors back, the check has to cope anyway
+    wire(&mut container.snarl, NodeId(2), 0, NodeId(1));
+
+    assert!(would_loop(&container.snarl, NodeId(2), NodeId(1)));
+    assert!(!would_loop(&container.snarl, NodeId(0), NodeId(1)));
+}
+
+#[test]
+fn entities_run_their_own_graphs() {
+    let mut harness = Harness::from_text("on_shoot -> deal_dmg");
+    let Ok(graph) = NodeGraph::from_dsl("on_shoot -> spread(2, 90deg) -> spawn_bullet") else {
+        panic!("pattern should parse");
+    };
+    let trigger = graph.trigger;
+    let enemy = harness.app.world.spawn(graph).id();
+
+    harness.trigger_as(
+        GraphOwner::Entity(enemy),
+        trigger,
+        0,
+        NodeEventData {
+            loc: Some(Vec2::ZERO),
+            dir: Some(Vec2::X),
+            ..default()
+        },
+    );
+    harness.step(3);
+
+    let events = harness.take_events();
+    assert_eq!(bullet_count(&events), 2, "{events:?}");
+    assert!(events.iter().all(|event| matches!(
+        event,
+        WorldEvent::SpawnBullet { owner, .. } if *owner == GraphOwner::Entity(enemy)
+    )));
+}
+
+#[test]
+fn events_for_a_missing_owner_are_dropped() {
+    let mut harness = Harness::from_text("on_shoot -> spawn_bullet");
+    let node = harness.app.world.resource::<SnarlContainer>().shoot_trigger;
+    let gone = harness.app.world.spawn_empty().id();
+    harness.app.world.despawn(gone);
+
+    harness.trigger_as(GraphOwner::Entity(gone), node, 0, NodeEventData::default());
+    harness.step(2);
+
+    assert!(harness.take_events().is_empty());
+}
+
+#[test]
+fn inventory_takes_all_or_nothing() {
+    let mut inventory = NodeInventory::default();
+    inventory.add(&Node::DealDmg, 1);
+
+    let err = inventory.take(&[Node::SpawnBullet, Node::SpawnBullet, Node::DealDmg]);
+    assert!(err.is_err());
+    assert_eq!(inventory.count(&Node::SpawnBullet), 1);
+    assert_eq!(inventory.count(&Node::DealDmg), 1);
+
+    // The trigger isn't part of the palette so it's free
+    assert_eq!(
+        inventory.take(&[Node::OnShoot, Node::SpawnBullet, Node::DealDmg]),
+        Ok(())
+    );
+    assert_eq!(inventory.count(&Node::SpawnBullet), 0);
+    assert!(!inventory.can_take(&[Node::DealDmg]));
+}
+
+#[test]
+fn inventory_counts_spreads_as_one_kind() {
+    let mut inventory = NodeInventory::default();
+    inventory.give_back(&[Node::Spread {
+        count: 7,
+        angle: 1.0,
+    }]);
+    assert_eq!(inventory.count(&Node::spread()), 1);
+}
+
+#[test]
+fn replacing_the_graph_refunds_the_old_one() {
+    let mut inventory = NodeInventory::default();
+    let old = [Node::OnShoot, Node::spread(), Node::SpawnBullet];
+    let new = [Node::OnShoot, Node::SpawnBullet, Node::SpawnBullet];
+
+    assert_eq!(inventory.swap(&old, &new), Ok(()));
+    assert_eq!(inventory.count(&Node::spread()), 1);
+    assert_eq!(inventory.count(&Node::SpawnBullet), 0);
+
+    // Not enough bullets, so nothing changes
+    assert!(inventory.swap(&new, &[Node::SpawnBullet; 4]).is_err());
+    assert_eq!(inventory.count(&Node::spread()), 1);
+    assert_eq!(inventory.count(&Node::SpawnBullet), 0);
+}
+
+#[test]
+fn macro_contents_cost_their_nodes() {
+    let mut container = dsl::parse("on_shoot -> spread(2, 10deg) -> spawn_bullet")
+        .unwrap_or_else(|err| panic!("{err}"));
+    let mut library = MacroLibrary::default();
+    let selection = container
+        .snarl
+        .node_ids()
+        .filter(|(_, node)| **node != Node::OnShoot)
+        .map(|(id, _)| id)
+        .collect();
+    library
+        .collapse(String::from("Fan"), &mut container.snarl, &selection)
+        .unwrap_or_else(|err| panic!("{err}"));
+
+    let mut contents: Vec<_> = library.contents(0).iter().map(Node::title).collect();
+    contents.sort_unstable();
+    assert_eq!(contents, ["Spawn Bullet", "Spread"]);
+    assert_eq!(library.graph_contents(&container.snarl).len(), 3);
+}
+
+#[test]
+fn enemy_patterns_only_take_working_graphs() {
+    let mut patterns = EnemyPatterns::default();
+    patterns.register("Caster", "on_shoot -> spawn_bullet");
+    // Registering again keeps what was there, like an edit made before
+    patterns.register("Caster", "on_shoot -> spread(2, 10deg) -> spawn_bullet");
+    assert_eq!(patterns.text("Caster"), Some("on_shoot -> spawn_bullet"));
+
+    assert!(patterns
+        .set("Caster", String::from("on_shoot -> "))
+        .is_err());
+    assert!(patterns.set("Nobody", String::from("on_shoot")).is_err());
+    assert_eq!(patterns.text("Caster"), Some("on_shoot -> spawn_bullet"));
+
+    let edited = String::from("on_shoot -> spread(3, 20deg) -> spawn_bullet");
+    assert_eq!(patterns.set("Caster", edited.clone()), Ok(()));
+    assert_eq!(patterns.text("Caster"), Some(edited.as_str()));
+    assert!(patterns.graph("Caster").is_some());
+    assert!(patterns.graph("Nobody").is_none());
+}
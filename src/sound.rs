@@ -0,0 +1,127 @@
+//! Sound effects and music, anything can ask for a sound with a `PlaySound` event.
+
+use bevy::audio::Volume;
+use bevy::utils::HashSet;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::prelude::*;
+use crate::{assets, MainState, PlayingState};
+
+pub struct SoundPlugin;
+
+impl Plugin for SoundPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<PlaySound>()
+            .init_resource::<AudioSettings>()
+            .add_systems(OnEnter(MainState::Playing), start_music)
+            .add_systems(
+                Update,
+                (
+                    play_sounds,
+                    set_music_volume.run_if(resource_changed::<AudioSettings>),
+                )
+                    .run_if(in_state(MainState::Playing)),
+            )
+            .add_systems(Update, volume_ui.run_if(in_state(PlayingState::Editor)));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Sound {
+    Shoot,
+    Hit,
+    Kill,
+    Connect,
+    Reject,
+}
+
+#[derive(Event, Debug, Clone, Copy)]
+pub struct PlaySound(pub Sound);
+
+#[derive(Resource, Debug)]
+struct AudioSettings {
+    master: f32,
+    music: f32,
+    effects: f32,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self {
+            master: 0.8,
+            music: 0.5,
+            effects: 0.8,
+        }
+    }
+}
+
+#[derive(Component)]
+struct Music;
+
+fn start_music(mut commands: Commands, sounds: Res<assets::Sounds>, settings: Res<AudioSettings>) {
+    commands.spawn((
+        Gc(MainState::Playing),
+        Music,
+        AudioSourceBundle {
+            source: sounds.music.clone(),
+            settings: PlaybackSettings::LOOP
+                .with_volume(Volume::new(settings.master * settings.music)),
+        },
+        Name::new("Music"),
+    ));
+}
+
+fn set_music_volume(music: Query<&AudioSink, With<Music>>, settings: Res<AudioSettings>) {
+    for sink in &music {
+        sink.set_volume(settings.master * settings.music);
+    }
+}
+
+fn play_sounds(
+    mut commands: Commands,
+    mut events: EventReader<PlaySound>,
+    sounds: Res<assets::Sounds>,
+    settings: Res<AudioSettings>,
+) {
+    // A lot of bullets hitting at once should still sound like one hit
+    let played: HashSet<_> = events.read().map(|event| event.0).collect();
+    for sound in played {
+        let source = match sound {
+            Sound::Shoot => &sounds.shoot,
+            Sound::Hit => &sounds.hit,
+            Sound::Kill => &sounds.kill,
+            Sound::Connect => &sounds.connect,
+            Sound::Reject => &sounds.reject,
+        };
+        commands.spawn(AudioSourceBundle {
+            source: source.clone(),
+            settings: PlaybackSettings::DESPAWN
+                .with_volume(Volume::new(settings.master * settings.effects)),
+        });
+    }
+}
+
+fn volume_ui(mut ctx: EguiContexts, mut settings: ResMut<AudioSettings>) {
+    egui::Window::new("Audio")
+        .anchor(egui::Align2::RIGHT_TOP, egui::Vec2::new(-8.0, 8.0))
+        .default_open(false)
+        .resizable(false)
+        .show(ctx.ctx_mut(), |ui| {
+            // Only touch the resource on a change, the music volume updates when it changes
+            let mut volumes = [settings.master, settings.music, settings.effects];
+            let mut changed = false;
+            for (volume, name) in volumes.iter_mut().zip(["Master", "Music", "Effects"]) {
+                changed |= ui
+                    .add(egui::Slider::new(volume, 0.0..=1.0).text(name))
+                    .changed();
+            }
+            if changed {
+                let [master, music, effects] = volumes;
+                *settings = AudioSettings {
+                    master,
+                    music,
+                    effects,
+                };
+            }
+        });
+}
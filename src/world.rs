@@ -0,0 +1,222 @@
+//! The world is split into chunks generated from the run seed, streamed in around the camera.
+//!
+//! A chunk always generates the same way for the same seed, so walking away and coming back
+//! gives you the same walls.
+
+use bevy::utils::HashMap;
+use bevy_prng::WyRand;
+use rand::{Rng, RngCore, SeedableRng};
+
+use crate::collision::{Collider, Collision, Layers};
+use crate::prelude::*;
+use crate::{world_running, MainState};
+
+const CHUNK_SIZE: f32 = 512.0;
+/// How many chunks around the camera chunk are kept loaded
+const VIEW_DISTANCE: i32 = 2;
+const MAX_WALLS: u32 = 3;
+/// Keeps the area around where the player spawns clear
+const SAFE_RADIUS: f32 = 250.0;
+
+pub struct WorldPlugin;
+
+impl Plugin for WorldPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RunSeed>()
+            .init_resource::<LoadedChunks>()
+            .add_systems(OnEnter(MainState::Playing), start_run)
+            .add_systems(Update, stream_chunks.run_if(in_state(MainState::Playing)))
+            .add_systems(Update, push_out_of_walls.run_if(world_running));
+    }
+}
+
+/// Everything random about the world is derived from this, so a seed always makes the same world
+#[derive(Resource, Default, Debug, Clone, Copy)]
+pub struct RunSeed(pub u64);
+
+/// Mixes the seed and a grid coordinate into a well spread hash
+pub fn grid_hash(seed: RunSeed, pos: IVec2) -> u64 {
+    // splitmix64 finalizer
+    let mut hash = seed.0
+        ^ u64::from(pos.x as u32).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ u64::from(pos.y as u32).wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    hash ^ (hash >> 31)
+}
+
+#[derive(Resource, Default)]
+struct LoadedChunks(HashMap<IVec2, Entity>);
+
+#[derive(Component)]
+pub struct Wall;
+
+struct WallSpec {
+    center: Vec2,
+    half_size: Vec2,
+}
+
+fn start_run(
+    mut commands: Commands,
+    mut rng: ResMut<GlobalEntropy<WyRand>>,
+    mut loaded: ResMut<LoadedChunks>,
+) {
+    commands.insert_resource(RunSeed(rng.next_u64()));
+    loaded.0.clear();
+}
+
+/// Walls in a chunk, relative to the chunks corner
+fn generate_chunk(seed: RunSeed, chunk: IVec2) -> Vec<WallSpec> {
+    let mut rng = WyRand::seed_from_u64(grid_hash(seed, chunk));
+    let corner = chunk.as_vec2() * CHUNK_SIZE;
+    (0..rng.gen_range(0..=MAX_WALLS))
+        .map(|_| {
+            // Mostly long thin walls, either way around
+            let long = rng.gen_range(48.0..160.0);
+            let short = rng.gen_range(16.0..32.0);
+            let half_size = if rng.gen_bool(0.5) {
+                Vec2::new(long, short)
+            } else {
+                Vec2::new(short, long)
+            };
+            let center = Vec2::new(
+                rng.gen_range(half_size.x..CHUNK_SIZE - half_size.x),
+                rng.gen_range(half_size.y..CHUNK_SIZE - half_size.y),
+            );
+            WallSpec { center, half_size }
+        })
+        .filter(|wall| {
+            let closest = Vec2::ZERO.clamp(
+                corner + wall.center - wall.half_size,
+                corner + wall.center + wall.half_size,
+            );
+            closest.length() > SAFE_RADIUS
+        })
+        .collect()
+}
+
+fn stream_chunks(
+    mut commands: Commands,
+    camera: Query<&Transform, With<Camera>>,
+    seed: Res<RunSeed>,
+    mut loaded: ResMut<LoadedChunks>,
+) {
+    let Ok(camera) = camera.get_single() else {
+        return;
+    };
+    let center = (camera.translation.truncate() / CHUNK_SIZE)
+        .floor()
+        .as_ivec2();
+    let in_view = |chunk: IVec2| (chunk - center).abs().max_element() <= VIEW_DISTANCE;
+
+    loaded.0.retain(|chunk, entity| {
+        if in_view(*chunk) {
+            return true;
+        }
+        commands.entity(*entity).despawn_recursive();
+        false
+    });
+
+    for x in -VIEW_DISTANCE..=VIEW_DISTANCE {
+        for y in -VIEW_DISTANCE..=VIEW_DISTANCE {
+            let chunk = center + IVec2::new(x, y);
+            if !loaded.0.contains_key(&chunk) {
+                let entity = spawn_chunk(&mut commands, *seed, chunk);
+                loaded.0.insert(chunk, entity);
+            }
+        }
+    }
+}
+
+fn spawn_chunk(commands: &mut Commands, seed: RunSeed, chunk: IVec2) -> Entity {
+    commands
+        .spawn((
+            Gc(MainState::Playing),
+            SpatialBundle::from_transform(Transform::from_translation(
+                (chunk.as_vec2() * CHUNK_SIZE).extend(0.0),
+            )),
+            Name::new(format!("Chunk {chunk}")),
+        ))
+        .with_children(|children| {
+            for wall in generate_chunk(seed, chunk) {
+                children.spawn((
+                    Wall,
+                    ShapeBundle {
+                        path: GeometryBuilder::build_as(&shapes::Rectangle {
+                            extents: wall.half_size * 2.0,
+                            origin: RectangleOrigin::Center,
+                        }),
+                        spatial: SpatialBundle::from_transform(Transform::from_translation(
+                            wall.center.extend(crate::ZIndex::Wall.into()),
+                        )),
+                        ..default()
+                    },
+                    Fill::color(Color::rgb(0.25, 0.22, 0.3)),
+                    Stroke::new(Color::rgb(0.1, 0.1, 0.12), 3.0),
+                    Collider::aabb(wall.half_size, Layers::WALL, Layers::default()),
+                    Name::new("Wall"),
+                ));
+            }
+        })
+        .id()
+}
+
+/// Walls are solid for the player and enemies, bullets bounce off them in `gameplay`
+fn push_out_of_walls(
+    mut collisions: EventReader<Collision>,
+    mut movers: Query<(&mut Transform, &Collider), Without<Wall>>,
+    walls: Query<(&GlobalTransform, &Collider), With<Wall>>,
+) {
+    for collision in collisions.read() {
+        let Some((mover, wall)) = collision.between(Layers::PLAYER | Layers::ENEMY, Layers::WALL)
+        else {
+            continue;
+        };
+        let (Ok((mut trans, collider)), Ok((wall_trans, wall_collider))) =
+            (movers.get_mut(mover), walls.get(wall))
+        else {
+            continue;
+        };
+        let push = collider.shape.separation(
+            trans.translation.truncate(),
+            wall_collider.shape,
+            wall_trans.translation().truncate(),
+        );
+        trans.translation += push.extend(0.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_generate_the_same_for_a_seed() {
+        let walls = |seed, chunk| {
+            generate_chunk(RunSeed(seed), chunk)
+                .into_iter()
+                .map(|wall| (wall.center, wall.half_size))
+                .collect::<Vec<_>>()
+        };
+        for x in -3..3 {
+            let chunk = IVec2::new(x, 2);
+            assert_eq!(walls(7, chunk), walls(7, chunk));
+        }
+        let differs = (-3..3).any(|x| walls(7, IVec2::new(x, 2)) != walls(8, IVec2::new(x, 2)));
+        assert!(differs);
+    }
+
+    #[test]
+    fn spawn_stays_clear() {
+        for seed in 0..50 {
+            for chunk in [IVec2::ZERO, IVec2::NEG_ONE, IVec2::X, IVec2::NEG_Y] {
+                for wall in generate_chunk(RunSeed(seed), chunk) {
+                    let center = chunk.as_vec2() * CHUNK_SIZE + wall.center;
+                    let closest =
+                        Vec2::ZERO.clamp(center - wall.half_size, center + wall.half_size);
+                    assert!(closest.length() > SAFE_RADIUS);
+                }
+            }
+        }
+    }
+}